@@ -64,7 +64,8 @@ pub fn scan_once() -> anyhow::Result<Vec<RuuviReading>> {
                         }
                     };
 
-                    let payload = encode_payload(&values);
+                    let rssi = i16::try_from(device.rssi()).ok();
+                    let payload = encode_payload(&values, rssi);
                     debug!("Ruuvi data: [{mac}] -> {payload}");
 
                     // Deduplicate by MAC: last advertisement wins.
@@ -103,11 +104,16 @@ struct SensorPayload {
     battery_low: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tx_power: Option<i8>,
+    /// Client ID of the reporting gateway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gateway: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rssi: Option<i16>,
 }
 
 /// Build the JSON payload from parsed sensor values.
 #[allow(clippy::cast_precision_loss)] // sensor value ranges are well within f32 precision
-fn encode_payload(v: &SensorValues) -> String {
+fn encode_payload(v: &SensorValues, rssi: Option<i16>) -> String {
     let temperature = v.temperature_as_millicelsius().map(|t| t as f32 / 1000.0);
     let humidity = v.humidity_as_ppm().map(|h| h as f32 / 10_000.0);
     let pressure = v.pressure_as_pascals().map(|p| p as f32 / 100.0);
@@ -135,6 +141,8 @@ fn encode_payload(v: &SensorValues) -> String {
         battery,
         battery_low,
         tx_power,
+        gateway: Some(config::MQTT_CLIENT_ID),
+        rssi,
     };
 
     serde_json::to_string(&payload).unwrap()
@@ -12,7 +12,7 @@ use crate::config;
 pub fn connect() -> anyhow::Result<(EspMqttClient<'static>, EspMqttConnection)> {
     let scheme = if config::MQTT_TLS { "mqtts" } else { "mqtt" };
     let broker_url = format!("{scheme}://{}:{}", config::MQTT_SERVER, config::MQTT_PORT);
-    let client_id = config::MQTT_CLIENT_ID.unwrap_or(config::DEVICE_HOSTNAME);
+    let client_id = config::MQTT_CLIENT_ID;
 
     if config::MQTT_TLS_INSECURE {
         log::warn!("TLS certificate verification is weakened (MQTT_TLS_INSECURE=true)");
@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Instant;
+
+use anyhow::{Context, bail};
+
+use crate::config;
+use crate::ruuvi::{BDAddr, SensorData};
+
+/// Threshold condition, e.g. `temperature > -15`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Condition {
+    pub quantity: Quantity,
+    pub operator: Operator,
+    pub threshold: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quantity {
+    Temperature,
+    Humidity,
+    Pressure,
+    SeaLevelPressure,
+    Battery,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    Above,
+    Below,
+}
+
+/// Evaluates the configured alert rules of the devices.
+#[derive(Default)]
+pub struct Alerts {
+    states: HashMap<(BDAddr, String), RuleState>,
+}
+
+#[derive(Debug, Default)]
+struct RuleState {
+    active: bool,
+    /// When the condition was first met, while waiting for the duration
+    pending_since: Option<Instant>,
+}
+
+impl Alerts {
+    /// Updates the alert states with a new reading. Returns true if any of
+    /// the states changed.
+    pub fn evaluate(
+        &mut self,
+        bdaddr: BDAddr,
+        rules: &[config::Alert],
+        sensor: &SensorData,
+        now: Instant,
+    ) -> bool {
+        let mut changed = false;
+        for rule in rules {
+            let Some(value) = rule.condition.quantity.value(sensor) else {
+                continue;
+            };
+            let state = self.states.entry((bdaddr, rule.key())).or_default();
+            if state.update(rule, value, now) {
+                log::info!(
+                    "Alert '{}' [{}]: {}",
+                    rule.name,
+                    bdaddr,
+                    if state.active { "on" } else { "off" }
+                );
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// Current alert states of the device, keyed by the state payload key.
+    pub fn states(&self, bdaddr: BDAddr, rules: &[config::Alert]) -> BTreeMap<String, bool> {
+        rules
+            .iter()
+            .map(|rule| {
+                let key = rule.key();
+                let active = self
+                    .states
+                    .get(&(bdaddr, key.clone()))
+                    .is_some_and(|state| state.active);
+                (key, active)
+            })
+            .collect()
+    }
+}
+
+impl RuleState {
+    fn update(&mut self, rule: &config::Alert, value: f32, now: Instant) -> bool {
+        if self.active {
+            if rule.condition.is_cleared(value, rule.hysteresis) {
+                self.active = false;
+                return true;
+            }
+        } else if rule.condition.is_met(value) {
+            let since = *self.pending_since.get_or_insert(now);
+            if now.duration_since(since) >= rule.duration {
+                self.active = true;
+                self.pending_since = None;
+                return true;
+            }
+        } else {
+            self.pending_since = None;
+        }
+        false
+    }
+}
+
+impl Condition {
+    fn is_met(self, value: f32) -> bool {
+        match self.operator {
+            Operator::Above => value > self.threshold,
+            Operator::Below => value < self.threshold,
+        }
+    }
+
+    /// The alert is cleared only once the value is past the threshold by the
+    /// hysteresis, to avoid flapping.
+    fn is_cleared(self, value: f32, hysteresis: f32) -> bool {
+        match self.operator {
+            Operator::Above => value <= self.threshold - hysteresis,
+            Operator::Below => value >= self.threshold + hysteresis,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let [quantity, operator, threshold] = parts[..] else {
+            bail!("Invalid condition '{s}', expected e.g. 'temperature > -15'");
+        };
+        Ok(Self {
+            quantity: quantity.parse()?,
+            operator: match operator {
+                ">" => Operator::Above,
+                "<" => Operator::Below,
+                _ => bail!("Invalid operator '{operator}', expected '>' or '<'"),
+            },
+            threshold: threshold
+                .parse()
+                .with_context(|| format!("Invalid threshold '{threshold}'"))?,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operator = match self.operator {
+            Operator::Above => ">",
+            Operator::Below => "<",
+        };
+        write!(f, "{} {} {}", self.quantity, operator, self.threshold)
+    }
+}
+
+impl Quantity {
+    fn value(self, sensor: &SensorData) -> Option<f32> {
+        match self {
+            Self::Temperature => sensor.temperature(),
+            Self::Humidity => sensor.humidity(),
+            Self::Pressure => sensor.pressure(),
+            Self::SeaLevelPressure => sensor.sea_level_pressure(),
+            Self::Battery => sensor.battery(),
+        }
+    }
+}
+
+impl FromStr for Quantity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "temperature" => Self::Temperature,
+            "humidity" => Self::Humidity,
+            "pressure" => Self::Pressure,
+            "sea_level_pressure" => Self::SeaLevelPressure,
+            "battery" => Self::Battery,
+            _ => bail!("Unknown quantity '{s}'"),
+        })
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Temperature => "temperature",
+            Self::Humidity => "humidity",
+            Self::Pressure => "pressure",
+            Self::SeaLevelPressure => "sea_level_pressure",
+            Self::Battery => "battery",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn make_rule(condition: &str, duration: Duration, hysteresis: f32) -> config::Alert {
+        config::Alert {
+            name: "Test".into(),
+            condition: condition.parse().unwrap(),
+            duration,
+            hysteresis,
+            device_class: "problem".into(),
+        }
+    }
+
+    #[test]
+    fn parses_conditions() {
+        let condition: Condition = "temperature > -15".parse().unwrap();
+        assert_eq!(
+            condition,
+            Condition {
+                quantity: Quantity::Temperature,
+                operator: Operator::Above,
+                threshold: -15.0,
+            }
+        );
+        assert_eq!(condition.to_string(), "temperature > -15");
+        assert!("humidity < 30".parse::<Condition>().is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_conditions() {
+        assert!("temperature".parse::<Condition>().is_err());
+        assert!("temperature >= 1".parse::<Condition>().is_err());
+        assert!("altitude > 1".parse::<Condition>().is_err());
+        assert!("temperature > warm".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn activates_immediately_without_duration() {
+        let rule = make_rule("humidity > 80", Duration::ZERO, 0.0);
+        let mut state = RuleState::default();
+        let now = Instant::now();
+        assert!(!state.update(&rule, 79.0, now));
+        assert!(state.update(&rule, 81.0, now));
+        assert!(state.active);
+    }
+
+    #[test]
+    fn activates_after_duration() {
+        let rule = make_rule("temperature > -15", Duration::from_mins(10), 0.0);
+        let mut state = RuleState::default();
+        let start = Instant::now();
+        assert!(!state.update(&rule, -10.0, start));
+        assert!(!state.update(&rule, -10.0, start + Duration::from_mins(9)));
+        assert!(state.update(&rule, -10.0, start + Duration::from_mins(10)));
+    }
+
+    #[test]
+    fn duration_restarts_when_condition_is_not_met() {
+        let rule = make_rule("temperature > -15", Duration::from_mins(10), 0.0);
+        let mut state = RuleState::default();
+        let start = Instant::now();
+        state.update(&rule, -10.0, start);
+        state.update(&rule, -20.0, start + Duration::from_mins(5));
+        assert!(!state.update(&rule, -10.0, start + Duration::from_mins(10)));
+        assert!(state.update(&rule, -10.0, start + Duration::from_mins(20)));
+    }
+
+    #[test]
+    fn clears_with_hysteresis() {
+        let rule = make_rule("temperature < 5", Duration::ZERO, 1.0);
+        let mut state = RuleState::default();
+        let now = Instant::now();
+        assert!(state.update(&rule, 4.0, now));
+        assert!(!state.update(&rule, 5.5, now));
+        assert!(state.active);
+        assert!(state.update(&rule, 6.0, now));
+        assert!(!state.active);
+    }
+}
@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::{DisplayFromStr, TimestampSeconds, serde_as};
+
+use crate::registry::write_atomically;
+use crate::ruuvi::BDAddr;
+
+/// Interval between the stored battery level samples
+const SAMPLE_INTERVAL: Duration = Duration::from_hours(1);
+/// Number of stored samples (30 days)
+const MAX_SAMPLES: usize = 30 * 24;
+/// Age of the oldest stored sample
+const MAX_AGE: Duration = Duration::from_hours(30 * 24);
+/// Minimum time span of the samples for a forecast
+const MIN_SPAN: Duration = Duration::from_hours(24);
+
+const SECONDS_PER_DAY: f32 = 86_400.0;
+
+/// Forecasts the battery replacement from the battery level trend.
+///
+/// The history is kept over restarts in the `battery.history` file if
+/// configured, otherwise the forecast is available one day after (re)starting
+/// the bridge.
+#[serde_as]
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct BatteryForecast {
+    #[serde_as(as = "BTreeMap<DisplayFromStr, VecDeque<(TimestampSeconds<i64>, _)>>")]
+    history: BTreeMap<BDAddr, VecDeque<(SystemTime, f32)>>,
+    /// A sample was recorded since loading or saving
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl BatteryForecast {
+    /// Reads the history. A missing file is an empty history.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(history) => serde_yaml::from_str(&history)
+                .with_context(|| format!("Failed to load {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Writes the history if a sample was recorded since the last save.
+    pub fn save(&mut self, path: &Path) -> Result<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        write_atomically(path, &serde_yaml::to_string(self).unwrap())?;
+        self.changed = false;
+        Ok(())
+    }
+
+    /// Records the battery level (%) and returns the estimated days until it
+    /// is empty, if the level is decreasing.
+    pub fn update(&mut self, bdaddr: BDAddr, level: f32, now: SystemTime) -> Option<f32> {
+        let age = |time: &SystemTime| now.duration_since(*time).unwrap_or_default();
+        let history = self.history.entry(bdaddr).or_default();
+        // Also drops the samples from before the bridge was offline for long
+        while history
+            .front()
+            .is_some_and(|(time, _)| age(time) >= MAX_AGE)
+        {
+            history.pop_front();
+        }
+        // A clock set backwards delays the next sample
+        if history.back().is_none_or(|(time, _)| {
+            now.duration_since(*time)
+                .is_ok_and(|age| age >= SAMPLE_INTERVAL)
+        }) {
+            if history.len() == MAX_SAMPLES {
+                history.pop_front();
+            }
+            history.push_back((now, level));
+            self.changed = true;
+        }
+
+        let (start, _) = *history.front()?;
+        if age(&start) < MIN_SPAN {
+            return None;
+        }
+        // Least squares slope of the level (% per day)
+        let points: Vec<(f32, f32)> = history
+            .iter()
+            .map(|(time, level)| {
+                (
+                    time.duration_since(start).unwrap_or_default().as_secs_f32() / SECONDS_PER_DAY,
+                    *level,
+                )
+            })
+            .collect();
+        #[allow(clippy::cast_precision_loss)] // at most MAX_SAMPLES
+        let n = points.len() as f32;
+        let mean_x = points.iter().map(|(x, _)| x).sum::<f32>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f32>() / n;
+        let covariance: f32 = points
+            .iter()
+            .map(|(x, y)| (x - mean_x) * (y - mean_y))
+            .sum();
+        let variance: f32 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+        let slope = covariance / variance;
+        (slope < 0.0).then(|| level / -slope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forecasts_from_decreasing_trend() {
+        let bdaddr = BDAddr::from([0u8; 6]);
+        let mut forecast = BatteryForecast::default();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_hours(24 * 365);
+        let mut days = None;
+        // 1% per day
+        for hour in 0..=48_u16 {
+            let level = 50.0 - f32::from(hour) / 24.0;
+            days = forecast.update(bdaddr, level, start + Duration::from_hours(hour.into()));
+            if hour < 24 {
+                assert_eq!(days, None);
+            }
+        }
+        assert!((days.unwrap() - 48.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn no_forecast_without_decrease() {
+        let bdaddr = BDAddr::from([0u8; 6]);
+        let mut forecast = BatteryForecast::default();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_hours(24 * 365);
+        for hour in 0..=48 {
+            let days = forecast.update(bdaddr, 80.0, start + Duration::from_hours(hour));
+            assert_eq!(days, None);
+        }
+    }
+
+    #[test]
+    fn keeps_history_over_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("battery.yaml");
+        let bdaddr: BDAddr = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let mut forecast = BatteryForecast::load(&path).unwrap();
+        forecast.update(bdaddr, 50.0, start);
+        forecast.save(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "AA:BB:CC:DD:EE:FF:\n- - 1700000000\n  - 50.0\n"
+        );
+
+        let mut forecast = BatteryForecast::load(&path).unwrap();
+        let days = forecast.update(bdaddr, 49.0, start + Duration::from_hours(24));
+        assert!((days.unwrap() - 49.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn drops_outdated_samples() {
+        let bdaddr = BDAddr::from([0u8; 6]);
+        let mut forecast = BatteryForecast::default();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        forecast.update(bdaddr, 90.0, start);
+        let days = forecast.update(bdaddr, 50.0, start + Duration::from_hours(31 * 24));
+        assert_eq!(days, None);
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::Result;
+
+use crate::Event;
+use crate::alerts::Alerts;
+use crate::battery::BatteryForecast;
+use crate::command::{Command, Request, Response};
+use crate::config::{self, CliOptions, Config};
+use crate::coordination::Coordinator;
+use crate::devices::{Devices, ThrottleResult};
+use crate::homeassistant::{Discovery, SensorData};
+use crate::mqtt::Mqtt;
+use crate::orientation::{self, OrientationTracker};
+use crate::registry::Registry;
+use crate::ruuvi::{self, BDAddr};
+use crate::sequence::SequenceTracker;
+use crate::validation::Validator;
+
+/// Handles the events from the BLE listener and MQTT.
+pub struct Bridge {
+    config: Config,
+    devices: Devices<BDAddr, config::Device>,
+    /// Last seen data format of each device, reported as the model to Home Assistant
+    data_formats: HashMap<BDAddr, u8>,
+    validator: Validator,
+    alerts: Alerts,
+    orientation: OrientationTracker,
+    battery_forecast: BatteryForecast,
+    sequences: SequenceTracker,
+    /// Set if the multi-gateway coordination is enabled
+    coordinator: Option<Coordinator>,
+    /// Discovery topics published by the other bridge instances, kept when
+    /// removing devices
+    other_manifests: HashMap<String, HashSet<String>>,
+    mqtt: Mqtt,
+}
+
+impl Bridge {
+    pub fn new(config: Config, mqtt: Mqtt) -> Self {
+        Self {
+            devices: Devices::new(&config.devices, config.mqtt.throttle),
+            data_formats: HashMap::new(),
+            validator: Validator::new(config.validation.clone()),
+            alerts: Alerts::default(),
+            orientation: OrientationTracker::default(),
+            battery_forecast: config
+                .battery
+                .history
+                .as_deref()
+                .map(BatteryForecast::load)
+                .transpose()
+                .unwrap_or_else(|err| {
+                    log::error!("{err:#}");
+                    None
+                })
+                .unwrap_or_default(),
+            sequences: SequenceTracker::default(),
+            other_manifests: HashMap::new(),
+            coordinator: config
+                .coordination
+                .enabled
+                .then(|| Coordinator::new(&config.mqtt.client_id, config.coordination.interval)),
+            config,
+            mqtt,
+        }
+    }
+
+    pub fn handle_event(&mut self, event: Event) {
+        use Event::{
+            HomeAssistantOnline, MqttCalibrateClosed, MqttCommand, MqttConnect, MqttDeviceUpdate,
+            MqttDiscoveryManifest, MqttRssiReport, RuuviUpdate, SourceFinished,
+        };
+
+        match event {
+            MqttConnect => {
+                log::info!("Connected to Mqtt. Publishing devices.");
+                self.mqtt.publish_availability(true);
+                self.publish_devices();
+            }
+            HomeAssistantOnline => {
+                log::info!("Home Assistant started. Publishing devices.");
+                self.publish_devices();
+            }
+            MqttDiscoveryManifest(gateway, topics) if gateway == self.config.mqtt.client_id => {
+                let current = self.discovery_topics();
+                for topic in topics.into_iter().filter(|t| !current.contains(t)) {
+                    self.remove_entity(topic);
+                }
+            }
+            MqttDiscoveryManifest(gateway, topics) => {
+                if topics.is_empty() {
+                    self.other_manifests.remove(&gateway);
+                } else {
+                    self.other_manifests
+                        .insert(gateway, topics.into_iter().collect());
+                }
+            }
+            MqttDeviceUpdate(bdaddr) => {
+                if let Some(device) = self.devices.mark_published(&bdaddr) {
+                    log::debug!("Updated from Mqtt: '{}' [{}]", device.name, bdaddr);
+                }
+            }
+            MqttCalibrateClosed(bdaddr) => match self.orientation.calibrate_closed(bdaddr) {
+                Some(closed) => log::info!(
+                    "Calibrated closed orientation [{bdaddr}]: {closed:?}. Set `orientation.closed` to keep it after restart."
+                ),
+                None => log::warn!("No orientation seen yet for calibration [{bdaddr}]"),
+            },
+            MqttRssiReport(gateway, report) => {
+                // The own reports are recorded when they are sent
+                if let Some(coordinator) = &mut self.coordinator
+                    && gateway != self.config.mqtt.client_id
+                {
+                    coordinator.receive(&gateway, &report, Instant::now());
+                }
+            }
+            MqttCommand(payload) => self.on_command(&payload),
+            RuuviUpdate(sensor) => self.on_ruuvi_update(sensor),
+            // Handled by the main loop
+            SourceFinished => {}
+        }
+    }
+
+    /// Re-reads the configuration file and applies changes to the `devices`.
+    ///
+    /// Other settings require a restart.
+    pub fn reload_config(&mut self, options: &CliOptions) -> Result<()> {
+        let new_config = Config::load(options)?;
+        self.update_devices(new_config.devices);
+        Ok(())
+    }
+
+    /// Replaces the configured devices, updating the Home Assistant discovery.
+    fn update_devices(&mut self, devices: HashMap<BDAddr, config::Device>) {
+        let changes = self.devices.update(&devices);
+
+        for bdaddr in &changes.removed {
+            if let Some(device) = self.config.devices.get(bdaddr) {
+                log::info!("Removing device: '{}' [{}]", device.name, bdaddr);
+                let topics: Vec<String> =
+                    Discovery::for_device(&self.config, *bdaddr, device, None)
+                        .iter()
+                        .map(|discovery| discovery.topic().to_string())
+                        .collect();
+                for topic in topics {
+                    self.remove_entity(topic);
+                }
+            }
+        }
+        self.config.devices = devices;
+        for bdaddr in changes.added.iter().chain(&changes.changed) {
+            let device = &self.config.devices[bdaddr];
+            log::info!("Publishing device: '{}' [{}]", device.name, bdaddr);
+            let data_format = self.data_formats.get(bdaddr).copied();
+            for discovery in Discovery::for_device(&self.config, *bdaddr, device, data_format) {
+                self.mqtt.publish_device(&discovery);
+            }
+        }
+        self.mqtt
+            .publish_discovery_manifest(&self.discovery_topics());
+    }
+
+    fn on_command(&mut self, payload: &[u8]) {
+        let (id, result) = match serde_json::from_slice::<Request>(payload) {
+            Ok(request) => {
+                log::info!("Received command: {:?}", request.command);
+                (request.id, self.run_command(request.command))
+            }
+            Err(err) => (None, Err(format!("Invalid command: {err}"))),
+        };
+        if let Err(err) = &result {
+            log::warn!("{err}");
+        }
+        let response = Response::new(id, &self.config.mqtt.client_id, result);
+        self.mqtt.publish_response(&response);
+    }
+
+    fn run_command(&mut self, command: Command) -> Result<(), String> {
+        match command {
+            Command::RepublishDiscovery => self.publish_devices(),
+            Command::PublishAll => self.devices.reset_throttle(),
+            Command::LogLevel { level } => log::set_max_level(level),
+            Command::Throttle { seconds } => {
+                self.config.mqtt.throttle = seconds;
+                self.devices.set_throttle(seconds);
+            }
+            Command::AddDevice { mac, name } => {
+                if self.config.devices.contains_key(&mac) {
+                    return Err(format!("Device already exists: [{mac}]"));
+                }
+                self.register(mac, &name)?;
+                let mut devices = self.config.devices.clone();
+                devices.insert(
+                    mac,
+                    config::Device {
+                        name,
+                        ..Default::default()
+                    },
+                );
+                self.update_devices(devices);
+            }
+            Command::RenameDevice { mac, name } => {
+                if !self.config.devices.contains_key(&mac) {
+                    return Err(format!("Unknown device: [{mac}]"));
+                }
+                self.register(mac, &name)?;
+                let mut devices = self.config.devices.clone();
+                devices.entry(mac).or_default().name = name;
+                self.update_devices(devices);
+            }
+        }
+        Ok(())
+    }
+
+    /// Persists the device name in the registry, if configured.
+    fn register(&self, bdaddr: BDAddr, name: &str) -> Result<(), String> {
+        let Some(path) = &self.config.registry else {
+            return Ok(());
+        };
+        let mut registry = Registry::load(path).map_err(|err| format!("{err:#}"))?;
+        registry.set_name(bdaddr, name);
+        registry.save(path).map_err(|err| format!("{err:#}"))
+    }
+
+    /// Publishes the RSSI of the tags seen since the previous report, if the
+    /// multi-gateway coordination is enabled.
+    pub fn report_rssi(&mut self) {
+        if let Some(coordinator) = &mut self.coordinator {
+            let report = coordinator.report(Instant::now());
+            if !report.is_empty() {
+                self.mqtt.publish_rssi_report(&report);
+            }
+        }
+    }
+
+    pub async fn shutdown(self, timeout: Duration) {
+        self.mqtt.shutdown(timeout).await;
+    }
+
+    fn on_ruuvi_update(&mut self, mut sensor: ruuvi::SensorData) {
+        // The same measurement is advertised multiple times. Only the known
+        // devices are tracked, not every tag in range.
+        if self.devices.get(&sensor.bdaddr).is_some()
+            && let Some(sequence_number) = sensor.measurement_sequence_number()
+            && !self.sequences.update(sensor.bdaddr, sequence_number)
+        {
+            log::trace!("Duplicate measurement [{}]", sensor.bdaddr);
+            return;
+        }
+        // Movement is published immediately, for door and tamper detection
+        let movement = sensor
+            .movement_counter()
+            .map(|counter| self.devices.detect_movement(&sensor.bdaddr, counter));
+        self.update_data_format(sensor.bdaddr, sensor.data_format);
+        let device = self.devices.get(&sensor.bdaddr);
+        if let Some(device) = device {
+            if let Some(coordinator) = &mut self.coordinator
+                && let Some(rssi) = sensor.rssi
+            {
+                coordinator.observe(sensor.bdaddr, rssi);
+            }
+            sensor.calibrate(&device.calibration);
+            sensor.set_altitude(self.config.altitude(device));
+        }
+
+        let device_name = device.map_or("?", |d| d.name.as_str());
+        if device.is_some()
+            && let Err(reason) = self.validator.validate(&sensor)
+        {
+            log::warn!(
+                "Rejected reading: '{}' [{}]: {}",
+                device_name,
+                sensor.bdaddr,
+                reason
+            );
+            return;
+        }
+        let rules = device.map_or(&[][..], |d| d.alerts.as_slice());
+        // Alert changes are published immediately, regardless of throttling
+        let alerts_changed = self
+            .alerts
+            .evaluate(sensor.bdaddr, rules, &sensor, Instant::now());
+        let was_open = self.orientation.is_open(sensor.bdaddr);
+        let orientation = device
+            .and_then(|d| d.orientation.as_ref())
+            .zip(sensor.acceleration())
+            .map(|(config, acceleration)| {
+                let open = self.orientation.update(sensor.bdaddr, config, acceleration);
+                (acceleration, open)
+            });
+        let open_changed = orientation.is_some_and(|(_, open)| open.is_some() && open != was_open);
+        // Only the known devices are kept in the history
+        let battery_days_remaining =
+            (self.config.battery.forecast && device.is_some()).then(|| {
+                let days = sensor.battery_level().and_then(|level| {
+                    self.battery_forecast
+                        .update(sensor.bdaddr, level, SystemTime::now())
+                });
+                if let Some(path) = &self.config.battery.history
+                    && let Err(err) = self.battery_forecast.save(path)
+                {
+                    log::error!("{err:#}");
+                }
+                days
+            });
+        let moved = movement == Some(true);
+        if moved {
+            log::info!("Movement detected: '{}' [{}]", device_name, sensor.bdaddr);
+        }
+        let is_owner = self
+            .coordinator
+            .as_mut()
+            .is_none_or(|coordinator| coordinator.is_owner(sensor.bdaddr, Instant::now()));
+        match self.devices.should_publish(&sensor.bdaddr) {
+            ThrottleResult::UnknownDevice => {
+                log::debug!("Unknown device: [{}]", sensor.bdaddr);
+            }
+            _ if !is_owner => {
+                log::debug!(
+                    "Published by another gateway: '{}' [{}]",
+                    device_name,
+                    sensor.bdaddr
+                );
+            }
+            ThrottleResult::Throttle if !alerts_changed && !moved && !open_changed => {
+                log::debug!("Throttled: '{}' [{}]", device_name, sensor.bdaddr);
+            }
+            ThrottleResult::Throttle | ThrottleResult::Update => {
+                log::info!("Updating: '{}' [{}]", device_name, sensor.bdaddr);
+                let mut data = SensorData::new(&sensor, &self.config.mqtt.base_topic);
+                data.rejected_readings = self.validator.rejected(sensor.bdaddr);
+                data.packet_loss = self.sequences.packet_loss(sensor.bdaddr);
+                data.gateway = Some(self.config.mqtt.client_id.clone());
+                data.alerts = self.alerts.states(sensor.bdaddr, rules);
+                data.movement = movement;
+                data.battery_days_remaining = battery_days_remaining;
+                if let Some((acceleration, open)) = orientation {
+                    data.pitch = Some(orientation::pitch(acceleration));
+                    data.roll = Some(orientation::roll(acceleration));
+                    data.open = Some(open);
+                }
+                self.mqtt.publish_sensor_data(data);
+            }
+        }
+    }
+
+    /// Re-publishes the device discovery when the data format of the tag
+    /// changes, because the model is derived from it.
+    fn update_data_format(&mut self, bdaddr: BDAddr, data_format: u8) {
+        let Some(device) = self.devices.get(&bdaddr) else {
+            return;
+        };
+        if self.data_formats.insert(bdaddr, data_format) != Some(data_format)
+            && self.config.homeassistant.device_discovery
+        {
+            for discovery in Discovery::for_device(&self.config, bdaddr, device, Some(data_format))
+            {
+                self.mqtt.publish_device(&discovery);
+            }
+        }
+    }
+
+    fn publish_devices(&mut self) {
+        for discovery in Discovery::all(&self.config, &self.data_formats) {
+            self.mqtt.publish_device(&discovery);
+        }
+        self.mqtt
+            .publish_discovery_manifest(&self.discovery_topics());
+    }
+
+    /// Home Assistant discovery topics of all the configured devices.
+    /// Removes the discovery topic, unless another bridge still publishes it.
+    fn remove_entity(&mut self, topic: String) {
+        if let Some(gateway) = self
+            .other_manifests
+            .iter()
+            .find_map(|(gateway, topics)| topics.contains(&topic).then_some(gateway))
+        {
+            log::info!("Keeping the entity published by '{gateway}': {topic}");
+            return;
+        }
+        log::info!("Removing entity: {topic}");
+        self.mqtt.remove_device(topic);
+    }
+
+    fn discovery_topics(&self) -> Vec<String> {
+        Discovery::all(&self.config, &HashMap::new())
+            .iter()
+            .map(|discovery| discovery.topic().to_string())
+            .collect()
+    }
+}
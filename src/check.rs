@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use serde_yaml::Value;
+
+use crate::coerce::Coerce;
+use crate::config::{self, CliOptions, Config};
+use crate::ruuvi::BDAddr;
+
+/// Configuration problem, located by its key path, e.g. `mqtt.port`.
+struct Issue {
+    path: Vec<String>,
+    message: String,
+}
+
+/// Validates the configuration strictly, printing the problems with their
+/// locations.
+pub fn check_config(options: &CliOptions) -> Result<()> {
+    let config_file = &options.config;
+    let value = Config::load_value(config_file)?;
+    let mut issues = check_devices(&value);
+
+    let mut unknown_keys = Vec::new();
+    let mut on_unknown = |path: serde_ignored::Path| unknown_keys.push(path.to_string());
+    let deserializer = serde_ignored::Deserializer::new(Coerce(value), &mut on_unknown);
+    let result: Result<Config, _> = serde_path_to_error::deserialize(deserializer);
+    for key in unknown_keys {
+        issues.push(Issue::new(&key, format!("Unknown key `{key}`")));
+    }
+    match result {
+        Ok(config) => issues.extend(check_semantics(&config)),
+        // Invalid MACs are already reported
+        Err(err) if err.path().to_string() != "devices" || issues.is_empty() => {
+            let path = err.path().to_string();
+            issues.push(Issue::new(&path, format!("`{path}`: {}", err.inner())));
+        }
+        Err(_) => {}
+    }
+
+    if issues.is_empty() {
+        println!("{}: OK", config_file.display());
+        return Ok(());
+    }
+    let sources = read_sources(config_file)?;
+    for issue in &issues {
+        eprintln!("{}", issue.locate(&sources));
+    }
+    bail!("Found {} problem(s) in the configuration", issues.len());
+}
+
+/// The device MACs are validated separately for more helpful errors.
+fn check_devices(value: &Value) -> Vec<Issue> {
+    let Some(devices) = value.get("devices").and_then(Value::as_mapping) else {
+        return Vec::new();
+    };
+    devices
+        .keys()
+        .filter_map(|key| {
+            let key = key.as_str().unwrap_or_default();
+            let err = key.parse::<BDAddr>().err()?;
+            Some(Issue::new(
+                &format!("devices.{key}"),
+                format!("Invalid MAC address `{key}`: {err}"),
+            ))
+        })
+        .collect()
+}
+
+fn check_semantics(config: &Config) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    let mut names: BTreeMap<String, Vec<BDAddr>> = BTreeMap::new();
+    for (bdaddr, device) in &config.devices {
+        names
+            .entry(device.name.to_lowercase())
+            .or_default()
+            .push(*bdaddr);
+    }
+    for bdaddrs in names.values_mut().filter(|bdaddrs| bdaddrs.len() > 1) {
+        bdaddrs.sort();
+        let name = &config.devices[&bdaddrs[0]].name;
+        for bdaddr in &bdaddrs[1..] {
+            issues.push(Issue::new(
+                &format!("devices.{bdaddr}.name"),
+                format!(
+                    "Duplicate device name `{name}` of {bdaddr}, also used by {}",
+                    bdaddrs[0]
+                ),
+            ));
+        }
+    }
+
+    for (bdaddr, device) in &config.devices {
+        let mut keys: BTreeMap<String, &str> = BTreeMap::new();
+        for alert in &device.alerts {
+            let path = format!("devices.{bdaddr}.alerts");
+            if config::snake_case(&alert.name).is_empty() {
+                issues.push(Issue::new(
+                    &path,
+                    format!("Alert name `{}` has no letters or digits", alert.name),
+                ));
+            } else if let Some(other) = keys.insert(alert.key(), &alert.name) {
+                issues.push(Issue::new(
+                    &path,
+                    format!(
+                        "Alert `{}` of {bdaddr} has the same key `{}` as `{other}`",
+                        alert.name,
+                        alert.key()
+                    ),
+                ));
+            }
+        }
+    }
+
+    if let Some(ca_file) = &config.mqtt.ca_file
+        && !ca_file.is_file()
+    {
+        issues.push(Issue::new(
+            "mqtt.ca_file",
+            format!("CA file not found: {}", ca_file.display()),
+        ));
+    }
+    if config.mqtt.tls_insecure && !config.mqtt.tls {
+        issues.push(Issue::new(
+            "mqtt.tls_insecure",
+            "`tls_insecure` requires `tls`".to_string(),
+        ));
+    }
+    issues
+}
+
+/// The configuration file and the device files with their contents.
+fn read_sources(config_file: &Path) -> Result<Vec<(PathBuf, String)>> {
+    let mut paths = vec![config_file.to_path_buf()];
+    paths.extend(config::device_files(&config::conf_d(config_file))?);
+    Ok(paths
+        .into_iter()
+        .filter_map(|path| {
+            let text = fs::read_to_string(&path).ok()?;
+            Some((path, text))
+        })
+        .collect())
+}
+
+impl Issue {
+    fn new(path: &str, message: String) -> Self {
+        Self {
+            path: split_path(path),
+            message,
+        }
+    }
+
+    /// Formats the issue with the file and line of the key, if found.
+    fn locate(&self, sources: &[(PathBuf, String)]) -> String {
+        if let Some(name) = config::env_override(&self.path) {
+            return format!("{name}: {}", self.message);
+        }
+        for (i, (path, text)) in sources.iter().enumerate() {
+            // The device files contain only the `devices` section
+            let keys = match self.path.split_first() {
+                Some((first, rest)) if i > 0 && first == "devices" => rest,
+                _ if i > 0 => continue,
+                _ => &self.path[..],
+            };
+            if let Some(line) = find_line(text, keys) {
+                return format!("{}:{line}: {}", path.display(), self.message);
+            }
+        }
+        match sources.first() {
+            Some((path, _)) => format!("{}: {}", path.display(), self.message),
+            None => self.message.clone(),
+        }
+    }
+}
+
+/// Splits a key path, keeping the MACs (containing dots only in invalid
+/// ones) intact as much as possible.
+fn split_path(path: &str) -> Vec<String> {
+    match path.split_once('.') {
+        Some(("devices", rest)) => {
+            // MACs are separated by colons, so the device key ends at the
+            // first dot after the last colon
+            let end = rest
+                .rfind(':')
+                .and_then(|colon| rest[colon..].find('.').map(|dot| colon + dot))
+                .unwrap_or(rest.len());
+            let mut keys = vec!["devices".to_string(), rest[..end].to_string()];
+            if end < rest.len() {
+                keys.extend(split_path(&rest[end + 1..]));
+            }
+            keys
+        }
+        _ => path
+            .split('.')
+            .filter(|key| !key.is_empty() && key.parse::<usize>().is_err())
+            .map(String::from)
+            .collect(),
+    }
+}
+
+/// Line number (1-based) of the nested key in a block style YAML document.
+fn find_line(text: &str, keys: &[String]) -> Option<usize> {
+    let mut lines = text.lines().enumerate();
+    let mut parent_indent = None;
+    let mut found = None;
+    for key in keys {
+        loop {
+            let (number, line) = lines.next()?;
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let indent = line.len() - trimmed.len();
+            if parent_indent.is_some_and(|parent| indent <= parent) {
+                return found;
+            }
+            let trimmed = trimmed
+                .trim_start_matches("- ")
+                .trim_start_matches(['"', '\'']);
+            if trimmed
+                .strip_prefix(key.as_str())
+                .is_some_and(|rest| rest.trim_start_matches(['"', '\'']).starts_with(':'))
+            {
+                parent_indent = Some(indent);
+                found = Some(number + 1);
+                break;
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_paths() {
+        assert_eq!(split_path("mqtt.port"), ["mqtt", "port"]);
+        assert_eq!(
+            split_path("devices.AA:BB:CC:DD:EE:FF.alerts.0.for"),
+            ["devices", "AA:BB:CC:DD:EE:FF", "alerts", "for"]
+        );
+        assert_eq!(split_path("devices.AA:BB"), ["devices", "AA:BB"]);
+    }
+
+    #[test]
+    fn finds_lines() {
+        let text = "mqtt:\n  server: localhost\n  # port: 1\n  port: x\ndevices:\n  AA:BB:CC:DD:EE:FF:\n    name: Sauna\n";
+        let keys = |path: &str| split_path(path);
+        assert_eq!(find_line(text, &keys("mqtt.port")), Some(4));
+        assert_eq!(
+            find_line(text, &keys("devices.AA:BB:CC:DD:EE:FF.name")),
+            Some(7)
+        );
+        assert_eq!(find_line(text, &keys("homeassistant")), None);
+    }
+}
@@ -0,0 +1,285 @@
+use serde::de::{self, DeserializeSeed, Deserializer, Unexpected, Visitor};
+use serde_yaml::{Error, Value, mapping};
+
+/// Deserializes a YAML value, parsing the strings to numbers and booleans if
+/// the target requires, e.g. for the values from the environment variables.
+/// The strings are kept as is for string targets.
+pub struct Coerce(pub Value);
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value, Error>
+            where
+                V: Visitor<'de>,
+            {
+                match self.0 {
+                    Value::String(s) => match s.parse() {
+                        Ok(value) => visitor.$visit(value),
+                        Err(_) => Err(de::Error::invalid_type(Unexpected::Str(&s), &visitor)),
+                    },
+                    value => value.$method(visitor),
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! deserialize_delegated {
+    ($($method:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value, Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.0.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for Coerce {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Value::Sequence(sequence) => visitor.visit_seq(SeqAccess(sequence.into_iter())),
+            Value::Mapping(mapping) => visitor.visit_map(MapAccess {
+                iter: mapping.into_iter(),
+                value: None,
+            }),
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    deserialize_delegated! {
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_unit,
+        deserialize_identifier,
+        deserialize_ignored_any,
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, name: &'static str, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+}
+
+struct SeqAccess(std::vec::IntoIter<Value>);
+
+impl<'de> de::SeqAccess<'de> for SeqAccess {
+    type Error = Error;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        self.0
+            .next()
+            .map(|value| seed.deserialize(Coerce(value)))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct MapAccess {
+    iter: mapping::IntoIter,
+    value: Option<Value>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess {
+    type Error = Error;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let Some((key, value)) = self.iter.next() else {
+            return Ok(None);
+        };
+        self.value = Some(value);
+        seed.deserialize(Coerce(key)).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = self.value.take().unwrap_or(Value::Null);
+        seed.deserialize(Coerce(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Options {
+        port: u16,
+        enabled: bool,
+        ratio: Option<f32>,
+        password: String,
+        names: Vec<String>,
+        limits: BTreeMap<String, i32>,
+    }
+
+    fn coerce(yaml: &str) -> Result<Options, Error> {
+        Options::deserialize(Coerce(serde_yaml::from_str(yaml).unwrap()))
+    }
+
+    #[test]
+    fn parses_strings_for_other_targets() {
+        let options = coerce(
+            "port: '1883'\nenabled: 'true'\nratio: '0.5'\npassword: '123456'\nnames: ['1']\nlimits: {a: '-3'}\n",
+        )
+        .unwrap();
+        assert_eq!(
+            options,
+            Options {
+                port: 1883,
+                enabled: true,
+                ratio: Some(0.5),
+                password: "123456".into(),
+                names: vec!["1".into()],
+                limits: BTreeMap::from([("a".into(), -3)]),
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_typed_values() {
+        let options = coerce(
+            "port: 1883\nenabled: false\nratio: null\npassword: '#s3cret'\nnames: []\nlimits: {}\n",
+        )
+        .unwrap();
+        assert_eq!(options.port, 1883);
+        assert!(!options.enabled);
+        assert_eq!(options.ratio, None);
+        assert_eq!(options.password, "#s3cret");
+    }
+
+    #[test]
+    fn rejects_invalid_strings() {
+        let err = coerce("port: broker\nenabled: true\npassword: x\nnames: []\nlimits: {}\n")
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid type: string \"broker\", expected u16"
+        );
+    }
+}
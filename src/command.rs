@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_with::{DisplayFromStr, DurationSeconds, formats::Flexible, serde_as};
+
+use crate::ruuvi::BDAddr;
+
+/// Request published to `{base_topic}/bridge/command`, e.g.
+/// `{"command": "throttle", "seconds": 30}`.
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    /// Echoed in the response, to match them
+    #[serde(default)]
+    pub id: Option<serde_json::Value>,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Re-publish the Home Assistant discovery messages
+    RepublishDiscovery,
+    /// Publish the next reading of every tag regardless of throttling
+    PublishAll,
+    LogLevel {
+        #[serde_as(as = "DisplayFromStr")]
+        level: log::LevelFilter,
+    },
+    Throttle {
+        #[serde_as(as = "DurationSeconds<u32, Flexible>")]
+        seconds: Duration,
+    },
+    /// Add a device, persisted in the `registry` file if configured
+    AddDevice {
+        #[serde_as(as = "DisplayFromStr")]
+        mac: BDAddr,
+        name: String,
+    },
+    /// Rename a device, persisted in the `registry` file if configured
+    RenameDevice {
+        #[serde_as(as = "DisplayFromStr")]
+        mac: BDAddr,
+        name: String,
+    },
+}
+
+/// Response published to `{base_topic}/bridge/response`.
+#[derive(Debug, Serialize)]
+pub struct Response {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<serde_json::Value>,
+    /// Client ID of the responding bridge
+    pub gateway: String,
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Response {
+    pub fn new(id: Option<serde_json::Value>, gateway: &str, result: Result<(), String>) -> Self {
+        let (status, error) = match result {
+            Ok(()) => ("ok", None),
+            Err(err) => ("error", Some(err)),
+        };
+        Self {
+            id,
+            gateway: gateway.to_string(),
+            status,
+            error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        let request: Request =
+            serde_json::from_str(r#"{"id": 1, "command": "throttle", "seconds": 30}"#).unwrap();
+        assert_eq!(request.id, Some(serde_json::json!(1)));
+        assert!(matches!(
+            request.command,
+            Command::Throttle { seconds } if seconds == Duration::from_secs(30)
+        ));
+
+        let request: Request = serde_json::from_str(
+            r#"{"command": "add_device", "mac": "AA:BB:CC:DD:EE:FF", "name": "Garage"}"#,
+        )
+        .unwrap();
+        assert!(matches!(request.command, Command::AddDevice { name, .. } if name == "Garage"));
+
+        let request: Request =
+            serde_json::from_str(r#"{"command": "log_level", "level": "debug"}"#).unwrap();
+        assert!(matches!(
+            request.command,
+            Command::LogLevel {
+                level: log::LevelFilter::Debug
+            }
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert!(serde_json::from_str::<Request>(r#"{"command": "reboot"}"#).is_err());
+    }
+
+    #[test]
+    fn serializes_responses() {
+        let response = Response::new(None, "gw1", Err("Unknown device".into()));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            serde_json::json!({"gateway": "gw1", "status": "error", "error": "Unknown device"})
+        );
+    }
+}
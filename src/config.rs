@@ -1,23 +1,52 @@
-use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
+use std::{
+    collections::HashMap,
+    env, fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use anyhow::{Context, Result};
-use clap::{CommandFactory, Parser};
+use anyhow::{Context, Result, bail};
+use clap::{CommandFactory, Parser, Subcommand};
 use derive_more::Debug;
 use rand::RngExt;
 use serde::Deserialize;
 use serde_with::{DisplayFromStr, DurationSeconds, formats::Flexible, serde_as};
+use serde_yaml::{Mapping, Value};
 use sysinfo::System;
 
+use crate::alerts::Condition;
+use crate::coerce::Coerce;
+use crate::registry::Registry;
 use crate::ruuvi::BDAddr;
 
 #[serde_as]
 #[derive(Debug, Deserialize)]
 pub struct Config {
     pub mqtt: Mqtt,
+    #[serde(default)]
+    pub homeassistant: HomeAssistant,
+    /// Default altitude (meters) of the devices, for sea-level pressure
+    pub altitude: Option<f32>,
+    #[serde(default)]
+    pub validation: Validation,
+    #[serde(default)]
+    pub battery: Battery,
+    #[serde(default)]
+    pub coordination: Coordination,
+    /// Writable file for the devices added or renamed at runtime
+    pub registry: Option<PathBuf>,
     #[serde_as(as = "HashMap<DisplayFromStr, _>")]
     pub devices: HashMap<BDAddr, Device>,
 }
 
+impl Config {
+    /// Altitude of the device, if configured either per-device or globally.
+    pub fn altitude(&self, device: &Device) -> Option<f32> {
+        device.altitude.or(self.altitude)
+    }
+}
+
 #[serde_as]
 #[derive(Debug, Deserialize)]
 pub struct Mqtt {
@@ -45,11 +74,276 @@ impl Mqtt {
     pub fn port(&self) -> u16 {
         self.port.unwrap_or(if self.tls { 8883 } else { 1883 })
     }
+
+    /// Retained `online`/`offline` state of this bridge instance.
+    pub fn availability_topic(&self) -> String {
+        format!("{}/bridge/{}/state", self.base_topic, self.client_id)
+    }
+
+    /// Retained list of the Home Assistant discovery topics published by this
+    /// bridge instance, used to clean up entities of removed devices.
+    pub fn discovery_manifest_topic(&self) -> String {
+        format!("{}/bridge/{}/discovery", self.base_topic, self.client_id)
+    }
+
+    /// Runtime commands to all the bridge instances.
+    pub fn command_topic(&self) -> String {
+        format!("{}/bridge/command", self.base_topic)
+    }
+
+    /// Responses to the runtime commands.
+    pub fn response_topic(&self) -> String {
+        format!("{}/bridge/response", self.base_topic)
+    }
+
+    /// RSSI reports of this bridge instance, for the multi-gateway coordination.
+    pub fn rssi_topic(&self) -> String {
+        format!("{}/bridge/{}/rssi", self.base_topic, self.client_id)
+    }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct HomeAssistant {
+    #[serde(default = "default_homeassistant_discovery_prefix")]
+    pub discovery_prefix: String,
+    #[serde(default = "default_homeassistant_status_topic")]
+    pub status_topic: String,
+    /// Publish one device discovery message per tag instead of one per entity
+    #[serde(default)]
+    pub device_discovery: bool,
+}
+
+impl Default for HomeAssistant {
+    fn default() -> Self {
+        Self {
+            discovery_prefix: default_homeassistant_discovery_prefix(),
+            status_topic: default_homeassistant_status_topic(),
+            device_discovery: false,
+        }
+    }
+}
+
+/// Coordination between multiple gateways
+#[derive(Debug, Deserialize)]
+pub struct Coordination {
+    /// Publish each tag only from the gateway that sees it best
+    #[serde(default)]
+    pub enabled: bool,
+    /// How often the RSSI of the tags is reported to the other gateways
+    #[serde(
+        default = "default_coordination_interval",
+        deserialize_with = "deserialize_interval"
+    )]
+    pub interval: Duration,
+}
+
+impl Default for Coordination {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval: default_coordination_interval(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Battery {
+    /// Publish the estimated days until the battery is empty
+    #[serde(default)]
+    pub forecast: bool,
+    /// Writable file for keeping the battery level history over restarts
+    pub history: Option<PathBuf>,
+}
+
+/// Plausibility checks for the readings
 #[derive(Clone, Debug, Deserialize)]
+pub struct Validation {
+    #[serde(default = "default_temperature_range")]
+    pub temperature: Range,
+    #[serde(default = "default_humidity_range")]
+    pub humidity: Range,
+    #[serde(default = "default_pressure_range")]
+    pub pressure: Range,
+    /// Reject values deviating more than this many standard deviations from
+    /// the rolling median. Disabled by default.
+    pub spike_sigma: Option<f32>,
+    /// Number of readings in the rolling window
+    #[serde(default = "default_spike_window")]
+    pub spike_window: NonZeroUsize,
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Self {
+            temperature: default_temperature_range(),
+            humidity: default_humidity_range(),
+            pressure: default_pressure_range(),
+            spike_sigma: None,
+            spike_window: default_spike_window(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Range {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Range {
+    pub fn contains(self, value: f32) -> bool {
+        (self.min..=self.max).contains(&value)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
 pub struct Device {
     pub name: String,
+    /// Suggested area in Home Assistant
+    pub area: Option<String>,
+    pub model: Option<String>,
+    pub hw_version: Option<String>,
+    /// Per-entity overrides, keyed by the entity, e.g. `temperature`
+    #[serde(default)]
+    pub entities: HashMap<String, Entity>,
+    #[serde(default)]
+    pub calibration: Calibration,
+    /// Altitude (meters), overrides the global `altitude`
+    pub altitude: Option<f32>,
+    #[serde(default)]
+    pub alerts: Vec<Alert>,
+    /// Open/closed state derived from the tilt, e.g. for doors and windows
+    pub orientation: Option<Orientation>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Orientation {
+    /// Home Assistant device class, e.g. `door`, `garage_door`, or `window`
+    #[serde(default = "default_orientation_device_class")]
+    pub device_class: String,
+    /// Acceleration vector (g) in the closed position. Can also be calibrated
+    /// by publishing to `{base_topic}/{MAC}/calibrate_closed`.
+    pub closed: Option<[f32; 3]>,
+    /// Tilt (degrees) from the closed position considered open
+    #[serde(default = "default_orientation_threshold")]
+    pub threshold: f32,
+}
+
+/// Threshold rule published as a binary sensor
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Alert {
+    pub name: String,
+    /// E.g. `temperature > -15`
+    #[serde_as(as = "DisplayFromStr")]
+    pub condition: Condition,
+    /// How long the condition must be met before the alert is activated, in
+    /// seconds or e.g. `10m`
+    #[serde(default, rename = "for", deserialize_with = "deserialize_duration")]
+    pub duration: Duration,
+    /// How far past the threshold the value must return to clear the alert
+    #[serde(default)]
+    pub hysteresis: f32,
+    /// Home Assistant device class, e.g. `problem`, `heat`, `cold`, or `moisture`
+    #[serde(default = "default_alert_device_class")]
+    pub device_class: String,
+}
+
+impl Alert {
+    /// Prefix of the alert keys, separating them from the other values
+    pub const KEY_PREFIX: &str = "alert";
+
+    /// Key of the alert in the state payload, e.g. `alert_freezer_warm`
+    pub fn key(&self) -> String {
+        format!("{}_{}", Self::KEY_PREFIX, snake_case(&self.name))
+    }
+}
+
+/// Converts a name to a key usable in the MQTT topics and the Home Assistant
+/// templates, e.g. `Too Humid (Sauna)` to `too_humid_sauna`.
+pub fn snake_case(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Deserializes a duration from seconds or a human-readable string like `10m`.
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Seconds(u64),
+        Text(String),
+    }
+
+    match Value::deserialize(deserializer)? {
+        Value::Seconds(seconds) => Ok(Duration::from_secs(seconds)),
+        Value::Text(text) => match text.parse() {
+            Ok(seconds) => Ok(Duration::from_secs(seconds)),
+            Err(_) => humantime::parse_duration(&text).map_err(serde::de::Error::custom),
+        },
+    }
+}
+
+/// Parses a non-zero duration like `5s` from the command line.
+fn parse_interval(value: &str) -> Result<humantime::Duration, String> {
+    let interval: humantime::Duration = value.parse().map_err(|err| format!("{err}"))?;
+    if interval.is_zero() {
+        return Err("the interval must be greater than zero".to_string());
+    }
+    Ok(interval)
+}
+
+/// Deserializes a non-zero duration in seconds.
+fn deserialize_interval<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let interval: Duration =
+        serde_with::As::<DurationSeconds<u32, Flexible>>::deserialize(deserializer)?;
+    if interval.is_zero() {
+        return Err(serde::de::Error::invalid_value(
+            serde::de::Unexpected::Unsigned(0),
+            &"a non-zero interval",
+        ));
+    }
+    Ok(interval)
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Entity {
+    /// Entity name without the device name
+    pub name: Option<String>,
+    pub icon: Option<String>,
+    pub enabled_by_default: Option<bool>,
+}
+
+/// Sensor calibration against a reference
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Calibration {
+    pub temperature: Option<Adjustment>,
+    pub humidity: Option<Adjustment>,
+    pub pressure: Option<Adjustment>,
+}
+
+/// Linear adjustment: `value * gain + offset`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub struct Adjustment {
+    #[serde(default = "default_adjustment_gain")]
+    pub gain: f32,
+    #[serde(default)]
+    pub offset: f32,
+}
+
+impl Adjustment {
+    pub fn apply(self, value: f32) -> f32 {
+        value.mul_add(self.gain, self.offset)
+    }
 }
 
 #[derive(Debug, Parser)]
@@ -60,16 +354,214 @@ pub struct CliOptions {
     pub config: PathBuf,
     #[arg(long, env, default_value = "INFO")]
     pub log_level: log::LevelFilter,
+    /// Write the MQTT messages to stdout as JSON lines instead of connecting
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Record the received advertisements to a capture file
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+    /// Replay the advertisements of a capture file instead of scanning
+    #[arg(long, value_name = "FILE", conflicts_with = "record")]
+    pub replay: Option<PathBuf>,
+    /// Generate readings for the configured devices instead of scanning
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        num_args = 0..=1,
+        default_missing_value = "1s",
+        value_parser = parse_interval,
+        conflicts_with_all = ["record", "replay"]
+    )]
+    pub simulate: Option<humantime::Duration>,
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Validate the configuration and exit
+    CheckConfig,
+    /// List the nearby Ruuvi tags
+    Scan {
+        /// How long to scan, e.g. `30s` or `2m`
+        #[arg(long, default_value = "30s")]
+        duration: humantime::Duration,
+        /// Print a `devices` section for the tags that aren't configured
+        #[arg(long)]
+        yaml: bool,
+    },
 }
 
 impl Config {
     pub fn load(options: &CliOptions) -> Result<Self> {
         let config_file = &options.config;
 
-        let config_str = fs::read_to_string(config_file)
-            .with_context(|| format!("Failed to read {}", config_file.display()))?;
-        serde_yaml::from_str(&config_str)
-            .with_context(|| format!("Failed to load {}", config_file.display()))
+        let value = Self::load_value(config_file)?;
+        let mut unknown_keys = Vec::new();
+        let mut config: Self =
+            serde_ignored::deserialize(Coerce(value), |path| unknown_keys.push(path.to_string()))
+                .with_context(|| format!("Failed to load {}", config_file.display()))?;
+        for key in unknown_keys {
+            log::warn!("Unknown configuration key: {key}");
+        }
+        if let Some(registry) = &config.registry {
+            Registry::load(registry)?.apply(&mut config.devices);
+        }
+        Ok(config)
+    }
+
+    /// Reads the configuration file, merging the device files, environment
+    /// variables, and `*_file` options.
+    pub fn load_value(config_file: &Path) -> Result<Value> {
+        let mut value = read_yaml(config_file)?;
+        merge_device_files(&mut value, &conf_d(config_file))?;
+        apply_env_overrides(&mut value, env::vars())?;
+        resolve_files(&mut value)?;
+        Ok(value)
+    }
+}
+
+/// Directory of additional device files of the configuration file
+pub fn conf_d(config_file: &Path) -> PathBuf {
+    config_file
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(CONF_D)
+}
+
+/// Directory of additional device files, next to the configuration file
+const CONF_D: &str = "conf.d";
+/// Prefix of the environment variables overriding config keys, e.g.
+/// `RUUVI2MQTT_MQTT__SERVER`
+const ENV_PREFIX: &str = "RUUVI2MQTT_";
+/// Keys that are paths as such, not `*_file` indirections
+const PATH_KEYS: &[&str] = &["ca_file"];
+
+fn read_yaml(path: &Path) -> Result<Value> {
+    let yaml =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_yaml::from_str(&yaml).with_context(|| format!("Failed to load {}", path.display()))
+}
+
+/// The `*.yaml` files of the directory in order, if it exists.
+pub fn device_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut paths = entries
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to read {}", dir.display()))?;
+    paths.retain(|path| {
+        path.extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml")
+    });
+    paths.sort();
+    Ok(paths)
+}
+
+/// Merges the devices from the device files of the directory.
+fn merge_device_files(config: &mut Value, dir: &Path) -> Result<()> {
+    let Value::Mapping(root) = config else {
+        bail!("Expected a mapping");
+    };
+    let devices = child_mapping(root, "devices")?;
+    for path in device_files(dir)? {
+        let Value::Mapping(file_devices) = read_yaml(&path)? else {
+            bail!("Expected a mapping of devices in {}", path.display());
+        };
+        for (bdaddr, device) in file_devices {
+            if devices.contains_key(&bdaddr) {
+                bail!(
+                    "Duplicate device {} in {}",
+                    bdaddr.as_str().unwrap_or("?"),
+                    path.display()
+                );
+            }
+            devices.insert(bdaddr, device);
+        }
+    }
+    Ok(())
+}
+
+/// Overrides config keys with `RUUVI2MQTT_` environment variables. Nested
+/// keys are separated by `__`. The values are strings, parsed when loading
+/// only if the option requires, e.g. a number.
+fn apply_env_overrides(
+    config: &mut Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> Result<()> {
+    let Value::Mapping(root) = config else {
+        bail!("Expected a mapping");
+    };
+    for (name, value) in vars {
+        let Some(path) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let keys: Vec<String> = path.split("__").map(str::to_lowercase).collect();
+        let Some((last, parents)) = keys.split_last() else {
+            continue;
+        };
+        let mut mapping = &mut *root;
+        for key in parents {
+            mapping = child_mapping(mapping, key)
+                .with_context(|| format!("Invalid environment variable {name}"))?;
+        }
+        mapping.insert(Value::from(last.as_str()), Value::String(value));
+    }
+    Ok(())
+}
+
+/// The `RUUVI2MQTT_` environment variable overriding the key path, if set.
+pub fn env_override(keys: &[String]) -> Option<String> {
+    env::vars().map(|(name, _)| name).find(|name| {
+        name.strip_prefix(ENV_PREFIX).is_some_and(|path| {
+            path.split("__")
+                .map(str::to_lowercase)
+                .eq(keys.iter().cloned())
+        })
+    })
+}
+
+/// Replaces `{key}_file` entries with `{key}` read from the file, e.g. for
+/// Docker secrets.
+fn resolve_files(config: &mut Value) -> Result<()> {
+    match config {
+        Value::Mapping(mapping) => {
+            let file_keys: Vec<String> = mapping
+                .keys()
+                .filter_map(Value::as_str)
+                .filter(|key| key.ends_with("_file") && !PATH_KEYS.contains(key))
+                .map(String::from)
+                .collect();
+            for file_key in file_keys {
+                let path = mapping
+                    .remove(file_key.as_str())
+                    .and_then(|path| path.as_str().map(PathBuf::from))
+                    .with_context(|| format!("Expected a path in {file_key}"))?;
+                let contents = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let key = file_key.trim_end_matches("_file");
+                mapping.insert(Value::from(key), Value::from(contents.trim_end()));
+            }
+            mapping.values_mut().try_for_each(resolve_files)
+        }
+        Value::Sequence(sequence) => sequence.iter_mut().try_for_each(resolve_files),
+        _ => Ok(()),
+    }
+}
+
+/// The mapping under `key`, created if missing.
+fn child_mapping<'a>(mapping: &'a mut Mapping, key: &str) -> Result<&'a mut Mapping> {
+    let entry = mapping.entry(Value::from(key)).or_insert(Value::Null);
+    if entry.is_null() {
+        *entry = Value::Mapping(Mapping::new());
+    }
+    match entry {
+        Value::Mapping(mapping) => Ok(mapping),
+        _ => bail!("Expected a mapping in {key}"),
     }
 }
 
@@ -88,6 +580,10 @@ fn default_mqtt_throttle() -> Duration {
     Duration::new(throttle, 0)
 }
 
+const fn default_coordination_interval() -> Duration {
+    Duration::from_secs(10)
+}
+
 fn default_mqtt_client_id() -> String {
     let suffix = System::host_name().unwrap_or_else(|| {
         log::warn!("Failed to read hostname. Generating random suffix for the client_id.");
@@ -100,6 +596,57 @@ fn default_mqtt_base_topic() -> String {
     String::from("ruuvi2mqtt")
 }
 
+// Operating ranges of the RuuviTag sensors
+
+const fn default_temperature_range() -> Range {
+    Range {
+        min: -40.0,
+        max: 85.0,
+    }
+}
+
+const fn default_humidity_range() -> Range {
+    Range {
+        min: 0.0,
+        max: 100.0,
+    }
+}
+
+const fn default_pressure_range() -> Range {
+    Range {
+        min: 500.0,
+        max: 1100.0,
+    }
+}
+
+const fn default_spike_window() -> NonZeroUsize {
+    NonZeroUsize::new(10).unwrap()
+}
+
+fn default_orientation_device_class() -> String {
+    String::from("door")
+}
+
+const fn default_orientation_threshold() -> f32 {
+    30.0
+}
+
+fn default_alert_device_class() -> String {
+    String::from("problem")
+}
+
+const fn default_adjustment_gain() -> f32 {
+    1.0
+}
+
+fn default_homeassistant_discovery_prefix() -> String {
+    String::from("homeassistant")
+}
+
+fn default_homeassistant_status_topic() -> String {
+    String::from("homeassistant/status")
+}
+
 #[allow(dead_code)] // used via derive_more #[debug(...)] attribute
 fn fmt_secret(value: Option<&String>) -> &str {
     match value {
@@ -168,4 +715,152 @@ mod tests {
         };
         assert_eq!(mqtt.port(), 9999);
     }
+
+    #[test]
+    fn mqtt_availability_topic_includes_client_id() {
+        let mqtt = Mqtt {
+            server: "localhost".into(),
+            port: None,
+            tls: false,
+            tls_insecure: false,
+            ca_file: None,
+            user: None,
+            password: None,
+            client_id: "gw1".into(),
+            base_topic: "ruuvi".into(),
+            throttle: Duration::from_mins(1),
+        };
+        assert_eq!(mqtt.availability_topic(), "ruuvi/bridge/gw1/state");
+    }
+
+    #[test]
+    fn homeassistant_defaults_when_not_configured() {
+        let config: Config =
+            serde_yaml::from_str("mqtt:\n  server: localhost\ndevices: {}\n").unwrap();
+        assert_eq!(config.homeassistant.discovery_prefix, "homeassistant");
+        assert_eq!(config.homeassistant.status_topic, "homeassistant/status");
+    }
+
+    #[test]
+    fn homeassistant_discovery_prefix_can_be_configured() {
+        let config: Config = serde_yaml::from_str(
+            "mqtt:\n  server: localhost\nhomeassistant:\n  discovery_prefix: ha\ndevices: {}\n",
+        )
+        .unwrap();
+        assert_eq!(config.homeassistant.discovery_prefix, "ha");
+        assert_eq!(config.homeassistant.status_topic, "homeassistant/status");
+    }
+
+    #[test]
+    fn calibration_defaults_to_unit_gain() {
+        let calibration: Calibration =
+            serde_yaml::from_str("temperature:\n  offset: -0.4\n").unwrap();
+        let temperature = calibration.temperature.unwrap();
+        assert!((temperature.apply(20.0) - 19.6).abs() < f32::EPSILON);
+        assert_eq!(calibration.humidity, None);
+    }
+
+    #[test]
+    fn parses_alerts() {
+        let device: Device = serde_yaml::from_str(
+            "name: Freezer\nalerts:\n  - name: Freezer Warm\n    condition: temperature > -15\n    for: 600\n",
+        )
+        .unwrap();
+        let alert = &device.alerts[0];
+        assert_eq!(alert.key(), "alert_freezer_warm");
+        assert_eq!(alert.condition.to_string(), "temperature > -15");
+        assert_eq!(alert.duration, Duration::from_mins(10));
+        assert_eq!(alert.device_class, "problem");
+    }
+
+    #[test]
+    fn parses_alert_durations() {
+        let duration = |value: &str| {
+            serde_yaml::from_str::<Alert>(&format!(
+                "name: Cold\ncondition: temperature < 0\nfor: {value}\n"
+            ))
+            .map(|alert| alert.duration)
+        };
+        assert_eq!(duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(duration("'90'").unwrap(), Duration::from_secs(90));
+        assert_eq!(duration("10m").unwrap(), Duration::from_mins(10));
+        assert_eq!(duration("1h 30m").unwrap(), Duration::from_mins(90));
+        assert!(duration("soon").is_err());
+    }
+
+    #[test]
+    fn alert_keys_are_sanitized() {
+        assert_eq!(snake_case("Battery"), "battery");
+        assert_eq!(snake_case("Too Humid (Sauna)"), "too_humid_sauna");
+        assert_eq!(snake_case("Door #1 / Garage-Side"), "door_1_garage_side");
+    }
+
+    #[test]
+    fn env_overrides_config_keys() {
+        let mut value: Value = serde_yaml::from_str("mqtt:\n  server: localhost\n").unwrap();
+        let vars = [
+            ("RUUVI2MQTT_MQTT__SERVER", "broker"),
+            ("RUUVI2MQTT_MQTT__PORT", "8883"),
+            ("RUUVI2MQTT_MQTT__USER", "123456"),
+            ("RUUVI2MQTT_MQTT__PASSWORD", "#s3cret"),
+            ("RUUVI2MQTT_HOMEASSISTANT__DEVICE_DISCOVERY", "true"),
+            ("OTHER", "ignored"),
+        ];
+        apply_env_overrides(
+            &mut value,
+            vars.into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string())),
+        )
+        .unwrap();
+        value
+            .as_mapping_mut()
+            .unwrap()
+            .insert("devices".into(), Value::Mapping(Mapping::new()));
+        let config = Config::deserialize(Coerce(value)).unwrap();
+        assert_eq!(config.mqtt.server, "broker");
+        assert_eq!(config.mqtt.port(), 8883);
+        assert_eq!(config.mqtt.user.as_deref(), Some("123456"));
+        assert_eq!(config.mqtt.password.as_deref(), Some("#s3cret"));
+        assert!(config.homeassistant.device_discovery);
+    }
+
+    #[test]
+    fn resolves_file_indirection() {
+        let dir = tempfile::tempdir().unwrap();
+        let password_file = dir.path().join("password");
+        fs::write(&password_file, "secret\n").unwrap();
+        let mut value: Value = serde_yaml::from_str(&format!(
+            "mqtt:\n  password_file: {}\n  ca_file: ca.pem\n",
+            password_file.display()
+        ))
+        .unwrap();
+        resolve_files(&mut value).unwrap();
+        assert_eq!(value["mqtt"]["password"], "secret");
+        assert_eq!(value["mqtt"]["ca_file"], "ca.pem");
+        assert!(value["mqtt"].get("password_file").is_none());
+    }
+
+    #[test]
+    fn merges_device_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        fs::write(
+            dir.join("garage.yaml"),
+            "AA:BB:CC:DD:EE:01:\n  name: Garage\n",
+        )
+        .unwrap();
+        fs::write(dir.join("notes.txt"), "not a device file").unwrap();
+        let mut value: Value =
+            serde_yaml::from_str("devices:\n  AA:BB:CC:DD:EE:02:\n    name: Sauna\n").unwrap();
+        merge_device_files(&mut value, dir).unwrap();
+        assert_eq!(value["devices"]["AA:BB:CC:DD:EE:01"]["name"], "Garage");
+        assert_eq!(value["devices"]["AA:BB:CC:DD:EE:02"]["name"], "Sauna");
+
+        fs::write(
+            dir.join("sauna.yaml"),
+            "AA:BB:CC:DD:EE:02:\n  name: Sauna\n",
+        )
+        .unwrap();
+        assert!(merge_device_files(&mut value, dir).is_err());
+    }
 }
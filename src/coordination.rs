@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ruuvi::BDAddr;
+
+/// Reports older than this many intervals are ignored, failing over to the
+/// next best gateway
+const STALE_INTERVALS: u32 = 3;
+/// How much stronger (dB) another gateway must see the tag to take over
+const HYSTERESIS: i16 = 3;
+
+/// RSSI of a tag as reported by a gateway
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Observation {
+    /// Average RSSI since the previous report
+    pub rssi: i16,
+    /// Whether the gateway publishes the tag
+    #[serde(default)]
+    pub owner: bool,
+}
+
+/// Elects the gateway that publishes each tag, based on the RSSI reports of
+/// all the gateways.
+///
+/// Every gateway periodically reports the average RSSI of the tags it sees,
+/// and whether it publishes them. The owner is derived only from these shared
+/// reports, so all the gateways agree on it: the reported owner keeps the tag
+/// unless another gateway sees it better by more than the hysteresis.
+pub struct Coordinator {
+    client_id: String,
+    interval: Duration,
+    /// RSSI samples of this gateway since the last report
+    samples: HashMap<BDAddr, Vec<i16>>,
+    /// Latest report of each tag by each gateway
+    reports: HashMap<BDAddr, HashMap<String, (Observation, Instant)>>,
+    /// Last elected owner of each tag, for logging the changes
+    owners: HashMap<BDAddr, String>,
+}
+
+impl Coordinator {
+    pub fn new(client_id: &str, interval: Duration) -> Self {
+        Self {
+            client_id: client_id.to_string(),
+            interval,
+            samples: HashMap::new(),
+            reports: HashMap::new(),
+            owners: HashMap::new(),
+        }
+    }
+
+    pub fn observe(&mut self, bdaddr: BDAddr, rssi: i16) {
+        self.samples.entry(bdaddr).or_default().push(rssi);
+    }
+
+    /// Average RSSI of the tags seen since the previous report, and whether
+    /// this gateway publishes them, to be published to the other gateways.
+    pub fn report(&mut self, now: Instant) -> HashMap<BDAddr, Observation> {
+        let samples: Vec<_> = self.samples.drain().collect();
+        let report: HashMap<BDAddr, Observation> = samples
+            .into_iter()
+            .map(|(bdaddr, samples)| {
+                let sum: i32 = samples.iter().copied().map(i32::from).sum();
+                #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+                // the average of i16 values fits in i16
+                let rssi = (sum / samples.len() as i32) as i16;
+                let owner = self.is_owner(bdaddr, now);
+                (bdaddr, Observation { rssi, owner })
+            })
+            .collect();
+        let client_id = self.client_id.clone();
+        self.receive(&client_id, &report, now);
+        report
+    }
+
+    /// Records the report of a gateway. The own reports are recorded when they
+    /// are sent, and ignored when received via MQTT.
+    pub fn receive(&mut self, gateway: &str, report: &HashMap<BDAddr, Observation>, now: Instant) {
+        for (bdaddr, observation) in report {
+            self.reports
+                .entry(*bdaddr)
+                .or_default()
+                .insert(gateway.to_string(), (*observation, now));
+        }
+    }
+
+    /// Whether this gateway should publish the tag. Publishes if no gateway
+    /// has reported the tag recently.
+    pub fn is_owner(&mut self, bdaddr: BDAddr, now: Instant) -> bool {
+        let stale = self.interval * STALE_INTERVALS;
+        let Some(reports) = self.reports.get_mut(&bdaddr) else {
+            return true;
+        };
+        reports.retain(|_, (_, seen)| now.duration_since(*seen) < stale);
+
+        let observations = || {
+            reports
+                .iter()
+                .map(|(id, (observation, _))| (id, observation))
+        };
+        let Some((best_id, best)) = strongest(observations()) else {
+            self.owners.remove(&bdaddr);
+            return true;
+        };
+        // Several gateways may claim the tag until their reports converge
+        let claimed = strongest(observations().filter(|(_, observation)| observation.owner));
+        let (owner, rssi) = match claimed {
+            Some((id, claimed)) if claimed.rssi + HYSTERESIS >= best.rssi => (id, claimed.rssi),
+            _ => (best_id, best.rssi),
+        };
+        if self.owners.get(&bdaddr) != Some(owner) {
+            log::info!("Tag [{bdaddr}] is published by '{owner}' ({rssi} dBm)");
+            self.owners.insert(bdaddr, owner.clone());
+        }
+        *owner == self.client_id
+    }
+}
+
+/// Strongest signal, ties are broken by the client ID.
+fn strongest<'a>(
+    observations: impl Iterator<Item = (&'a String, &'a Observation)>,
+) -> Option<(&'a String, &'a Observation)> {
+    observations.max_by(|(a_id, a), (b_id, b)| a.rssi.cmp(&b.rssi).then_with(|| b_id.cmp(a_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(10);
+
+    fn bdaddr() -> BDAddr {
+        BDAddr::from([0u8; 6])
+    }
+
+    fn report(rssi: i16, owner: bool) -> HashMap<BDAddr, Observation> {
+        HashMap::from([(bdaddr(), Observation { rssi, owner })])
+    }
+
+    #[test]
+    fn publishes_without_reports() {
+        let mut coordinator = Coordinator::new("gw1", INTERVAL);
+        assert!(coordinator.is_owner(bdaddr(), Instant::now()));
+    }
+
+    #[test]
+    fn best_gateway_publishes() {
+        let now = Instant::now();
+        let mut coordinator = Coordinator::new("gw1", INTERVAL);
+        coordinator.observe(bdaddr(), -80);
+        coordinator.observe(bdaddr(), -90);
+        assert_eq!(coordinator.report(now), report(-85, true));
+        assert!(coordinator.is_owner(bdaddr(), now));
+
+        coordinator.receive("gw2", &report(-70, false), now);
+        assert!(!coordinator.is_owner(bdaddr(), now));
+    }
+
+    #[test]
+    fn small_differences_do_not_change_the_owner() {
+        let now = Instant::now();
+        let mut coordinator = Coordinator::new("gw1", INTERVAL);
+        coordinator.receive("gw1", &report(-70, true), now);
+        coordinator.receive("gw2", &report(-68, false), now);
+        assert!(coordinator.is_owner(bdaddr(), now));
+        coordinator.receive("gw2", &report(-66, false), now);
+        assert!(!coordinator.is_owner(bdaddr(), now));
+    }
+
+    #[test]
+    fn gateways_agree_on_the_owner() {
+        let now = Instant::now();
+        let mut gw1 = Coordinator::new("gw1", INTERVAL);
+        let mut gw2 = Coordinator::new("gw2", INTERVAL);
+        // Both publish the tag before seeing each other's reports
+        gw1.observe(bdaddr(), -70);
+        gw2.observe(bdaddr(), -70);
+        let report1 = gw1.report(now);
+        let report2 = gw2.report(now);
+        assert!(report1[&bdaddr()].owner && report2[&bdaddr()].owner);
+        gw1.receive("gw2", &report2, now);
+        gw2.receive("gw1", &report1, now);
+        assert!(gw1.is_owner(bdaddr(), now));
+        assert!(!gw2.is_owner(bdaddr(), now));
+
+        // The owner is kept by both, regardless of their own history
+        let later = now + INTERVAL;
+        gw1.observe(bdaddr(), -71);
+        gw2.observe(bdaddr(), -69);
+        let report1 = gw1.report(later);
+        let report2 = gw2.report(later);
+        assert_eq!(report1, report(-71, true));
+        assert_eq!(report2, report(-69, false));
+        gw1.receive("gw2", &report2, later);
+        gw2.receive("gw1", &report1, later);
+        assert!(gw1.is_owner(bdaddr(), later));
+        assert!(!gw2.is_owner(bdaddr(), later));
+    }
+
+    #[test]
+    fn fails_over_when_reports_stop() {
+        let now = Instant::now();
+        let mut coordinator = Coordinator::new("gw1", INTERVAL);
+        coordinator.receive("gw1", &report(-80, false), now);
+        coordinator.receive("gw2", &report(-60, true), now);
+        assert!(!coordinator.is_owner(bdaddr(), now));
+
+        let later = now + INTERVAL * 2;
+        coordinator.receive("gw1", &report(-80, false), later);
+        assert!(!coordinator.is_owner(bdaddr(), later));
+        let later = now + INTERVAL * 3;
+        assert!(coordinator.is_owner(bdaddr(), later));
+    }
+}
@@ -10,6 +10,16 @@ pub struct Devices<K, V> {
 struct DeviceData<T> {
     data: T,
     last_updated: Option<Instant>,
+    /// Last seen movement counter
+    movement_counter: Option<u32>,
+}
+
+/// Device IDs affected by [`Devices::update`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct Changes<K> {
+    pub added: Vec<K>,
+    pub changed: Vec<K>,
+    pub removed: Vec<K>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -59,6 +69,67 @@ where
         device.mark_published();
         Some(device.data())
     }
+
+    pub fn set_throttle(&mut self, throttle: Duration) {
+        self.throttle = throttle;
+    }
+
+    /// Publishes the next update of every device regardless of throttling.
+    pub fn reset_throttle(&mut self) {
+        for device in self.devices.values_mut() {
+            device.last_updated = None;
+        }
+    }
+
+    /// Records the movement counter of the device. Returns true if the counter
+    /// changed since the previous reading.
+    pub fn detect_movement(&mut self, device_id: &K, counter: u32) -> bool {
+        let Some(device) = self.devices.get_mut(device_id) else {
+            return false;
+        };
+        // The counter wraps around, so any change means movement
+        device
+            .movement_counter
+            .replace(counter)
+            .is_some_and(|previous| previous != counter)
+    }
+}
+
+impl<K, V> Devices<K, V>
+where
+    K: Eq + Copy + Hash,
+    V: Clone + PartialEq,
+{
+    /// Replaces the device set, keeping the throttle state of the devices that remain.
+    pub fn update(&mut self, devices: &HashMap<K, V>) -> Changes<K> {
+        let mut changes = Changes {
+            added: Vec::new(),
+            changed: Vec::new(),
+            removed: Vec::new(),
+        };
+
+        self.devices.retain(|id, _| {
+            let keep = devices.contains_key(id);
+            if !keep {
+                changes.removed.push(*id);
+            }
+            keep
+        });
+        for (id, data) in devices {
+            match self.devices.get_mut(id) {
+                None => {
+                    self.devices.insert(*id, DeviceData::new(data));
+                    changes.added.push(*id);
+                }
+                Some(device) if device.data != *data => {
+                    device.data = data.clone();
+                    changes.changed.push(*id);
+                }
+                Some(_) => {}
+            }
+        }
+        changes
+    }
 }
 
 impl<T: std::clone::Clone> DeviceData<T> {
@@ -66,6 +137,7 @@ impl<T: std::clone::Clone> DeviceData<T> {
         Self {
             data: data.clone(),
             last_updated: None,
+            movement_counter: None,
         }
     }
 
@@ -172,6 +244,40 @@ mod tests {
         assert_eq!(result.as_deref(), Some("data-1"));
     }
 
+    #[test]
+    fn update_reports_added_changed_and_removed_devices() {
+        let mut devs = make_devices(&[1, 2, 3], Duration::from_mins(1));
+        let new: HashMap<u32, String> = [
+            (1, "data-1".to_string()),
+            (2, "renamed".to_string()),
+            (4, "data-4".to_string()),
+        ]
+        .into();
+
+        let changes = devs.update(&new);
+        assert_eq!(
+            changes,
+            Changes {
+                added: vec![4],
+                changed: vec![2],
+                removed: vec![3],
+            }
+        );
+        assert_eq!(devs.get(&2).map(String::as_str), Some("renamed"));
+        assert_eq!(devs.get(&3), None);
+        assert_eq!(devs.should_publish(&4), ThrottleResult::Update);
+    }
+
+    #[test]
+    fn update_keeps_throttle_state() {
+        let mut devs = make_devices(&[1], Duration::from_mins(1));
+        devs.mark_published(&1);
+
+        let new: HashMap<u32, String> = [(1, "renamed".to_string())].into();
+        devs.update(&new);
+        assert_eq!(devs.should_publish(&1), ThrottleResult::Throttle);
+    }
+
     #[test]
     fn cross_instance_throttle_flow() {
         // Simulates: BLE scan → should_publish(Update) → publish → MQTT arrives → mark_published() → BLE scan → should_publish(Throttle)
@@ -186,4 +292,22 @@ mod tests {
         // Next BLE reading is throttled
         assert_eq!(devs.should_publish(&1), ThrottleResult::Throttle);
     }
+
+    #[test]
+    fn test_detect_movement() {
+        let mut devs = Devices::new(&HashMap::from([(1, 1)]), Duration::from_secs(1));
+        assert!(!devs.detect_movement(&1, 255));
+        assert!(!devs.detect_movement(&1, 255));
+        assert!(devs.detect_movement(&1, 0));
+        assert!(!devs.detect_movement(&2, 1));
+    }
+
+    #[test]
+    fn test_reset_throttle() {
+        let mut devs = Devices::new(&HashMap::from([(1, 1)]), Duration::from_secs(1));
+        devs.mark_published(&1);
+        assert_eq!(devs.should_publish(&1), ThrottleResult::Throttle);
+        devs.reset_throttle();
+        assert_eq!(devs.should_publish(&1), ThrottleResult::Update);
+    }
 }
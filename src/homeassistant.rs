@@ -1,12 +1,29 @@
 use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::str;
 
 use crate::config;
 use crate::ruuvi::{self, BDAddr};
 
+/// Seconds until the movement sensor turns off after detected movement
+const MOVEMENT_OFF_DELAY: u32 = 5;
+
+/// A retained Home Assistant MQTT discovery message.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+#[allow(clippy::large_enum_variant)] // short-lived, only built for publishing
+pub enum Discovery<'a> {
+    /// Single entity config (one message per sensor value)
+    Entity(Device<'a>),
+    /// Device config with all the entities as components
+    Device(DeviceDiscovery<'a>),
+}
+
 #[derive(Debug, Serialize)]
 #[allow(clippy::struct_field_names)] // field names intentionally mirror the HA MQTT topic structure
 pub struct Device<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    platform: Option<&'a str>,
     name: String,
     unique_id: String,
     state_class: &'a str,
@@ -17,16 +34,79 @@ pub struct Device<'a> {
     payload_info: Option<PayloadInfo>,
     #[serde(flatten)]
     device_type: DeviceType<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    icon: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    enabled_by_default: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<DeviceInfo<'a>>,
+    /// Availability of the publishing bridge
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    availability: Vec<Availability>,
+    #[serde(skip)]
+    pub topic: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Availability {
+    topic: String,
+    payload_available: &'static str,
+    payload_not_available: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeviceDiscovery<'a> {
     device: DeviceInfo<'a>,
+    origin: Origin,
+    components: BTreeMap<String, Component<'a>>,
     #[serde(skip)]
     pub topic: String,
 }
 
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Component<'a> {
+    Sensor(Box<Device<'a>>),
+    Connectivity(Connectivity<'a>),
+}
+
+/// Bridge availability as a binary sensor.
+#[derive(Debug, Serialize)]
+struct Connectivity<'a> {
+    platform: &'a str,
+    name: &'a str,
+    unique_id: String,
+    device_class: &'a str,
+    entity_category: &'a str,
+    state_topic: String,
+    payload_on: &'a str,
+    payload_off: &'a str,
+}
+
 #[derive(Clone, Debug, Serialize)]
 pub struct DeviceInfo<'a> {
     name: String,
     identifiers: Vec<String>,
     manufacturer: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hw_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sw_version: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggested_area: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    connections: Vec<(&'a str, String)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    via_device: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Origin {
+    name: &'static str,
+    sw_version: &'static str,
+    support_url: &'static str,
 }
 
 #[derive(Clone, Copy, Debug, Default, Serialize)]
@@ -41,8 +121,18 @@ pub struct DeviceType<'a> {
     pub unit_of_measurement: Option<&'a str>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub entity_category: Option<&'a str>,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Default icon, can be overridden per device
+    #[serde(skip)]
     pub icon: Option<&'a str>,
+    /// Defaults to `measurement`
+    #[serde(skip)]
+    pub state_class: Option<&'a str>,
+    /// Seconds after which a momentary binary sensor turns off
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub off_delay: Option<u32>,
+    /// Namespace of the key, e.g. for the alerts
+    #[serde(skip)]
+    pub key_prefix: Option<&'a str>,
 }
 
 #[derive(Debug, Serialize)]
@@ -51,10 +141,41 @@ pub struct SensorData {
     pub topic: String,
     humidity: Option<f32>,
     pressure: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sea_level_pressure: Option<f32>,
     temperature: Option<f32>,
     battery: Option<f32>,
     battery_low: Option<bool>,
+    battery_level: Option<f32>,
+    /// Only published if the forecast is enabled
+    #[allow(clippy::option_option)] // `Some(None)` is published as null
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery_days_remaining: Option<Option<f32>>,
     tx_power: Option<i8>,
+    movement_counter: Option<u32>,
+    /// Whether the tag moved since the previous reading
+    pub movement: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pitch: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub roll: Option<f32>,
+    /// Published with orientation, as null until the closed position is known
+    #[allow(clippy::option_option)] // `Some(None)` is published as null
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub open: Option<Option<bool>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    calibrated: Vec<&'static str>,
+    pub rejected_readings: u64,
+    /// Missed measurements (%), from the sequence numbers
+    pub packet_loss: Option<f32>,
+    /// Client ID of the reporting gateway
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rssi: Option<i16>,
+    /// Alert states, keyed by the alert
+    #[serde(flatten)]
+    pub alerts: BTreeMap<String, bool>,
 }
 
 #[derive(Debug, Serialize)]
@@ -63,34 +184,220 @@ pub struct PayloadInfo {
     payload_off: bool,
 }
 
+impl<'a> Discovery<'a> {
+    /// Discovery messages of all the configured devices.
+    ///
+    /// `data_formats` contains the last seen data format of each device, used
+    /// to report the device model.
+    pub fn all(config: &'a config::Config, data_formats: &HashMap<BDAddr, u8>) -> Vec<Self> {
+        let mut all: Vec<Self> = config
+            .devices
+            .iter()
+            .flat_map(|(bdaddr, device)| {
+                Self::for_device(config, *bdaddr, device, data_formats.get(bdaddr).copied())
+            })
+            .collect();
+        if config.homeassistant.device_discovery {
+            all.push(Self::Device(DeviceDiscovery::bridge(config)));
+        }
+        all
+    }
+
+    pub fn for_device(
+        config: &'a config::Config,
+        bdaddr: BDAddr,
+        device: &'a config::Device,
+        data_format: Option<u8>,
+    ) -> Vec<Self> {
+        if config.homeassistant.device_discovery {
+            vec![Self::Device(DeviceDiscovery::new(
+                config,
+                bdaddr,
+                device,
+                data_format,
+            ))]
+        } else {
+            Device::for_device(config, bdaddr, device)
+                .into_iter()
+                .map(Self::Entity)
+                .collect()
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        match self {
+            Self::Entity(device) => &device.topic,
+            Self::Device(device) => &device.topic,
+        }
+    }
+}
+
 impl<'a> Device<'a> {
-    pub fn all(config: &config::Config) -> Vec<Device<'a>> {
-        let mut devices = Vec::new();
-
-        for (bdaddr, device) in &config.devices {
-            let id = bdaddr.to_string_no_delim();
-
-            for device_type in DeviceType::all() {
-                let state_topic = format!("{}/{}", config.mqtt.base_topic, id);
-                let snake_name = device_type.name.to_lowercase().replace(' ', "_");
-                devices.push(Self {
-                    name: format!("{} {}", device.name, device_type.name),
-                    unique_id: format!("ruuvi_{id}_{snake_name}"),
-                    state_class: "measurement",
-                    state_topic: state_topic.clone(),
-                    json_attributes_topic: state_topic,
-                    value_template: format!("{{{{ value_json.{snake_name} }}}}"),
-                    payload_info: PayloadInfo::from(device_type),
-                    device_type: *device_type,
-                    device: DeviceInfo::new(device.name.clone(), *bdaddr),
-                    topic: format!(
-                        "homeassistant/{}/ruuvi_{}/{}/config",
-                        device_type.component, id, snake_name
-                    ),
-                });
-            }
+    pub fn for_device(
+        config: &'a config::Config,
+        bdaddr: BDAddr,
+        device: &'a config::Device,
+    ) -> Vec<Device<'a>> {
+        let id = bdaddr.to_string_no_delim();
+
+        DeviceType::for_device(config, device)
+            .iter()
+            .map(|device_type| Self {
+                device: Some(DeviceInfo::new(device, bdaddr)),
+                ..Self::new(config, &id, device, device_type)
+            })
+            .collect()
+    }
+
+    fn new(
+        config: &config::Config,
+        id: &str,
+        device: &config::Device,
+        device_type: &DeviceType<'a>,
+    ) -> Self {
+        let state_topic = format!("{}/{}", config.mqtt.base_topic, id);
+        let snake_name = device_type.snake_name();
+        let entity = device.entities.get(&snake_name);
+        Self {
+            platform: None,
+            name: format!("{} {}", device.name, entity_name(device, device_type)),
+            unique_id: format!("ruuvi_{id}_{snake_name}"),
+            state_class: device_type.state_class.unwrap_or("measurement"),
+            state_topic: state_topic.clone(),
+            json_attributes_topic: state_topic,
+            value_template: format!("{{{{ value_json.{snake_name} }}}}"),
+            payload_info: PayloadInfo::from(device_type),
+            device_type: *device_type,
+            icon: entity
+                .and_then(|e| e.icon.clone())
+                .or_else(|| device_type.icon.map(String::from)),
+            enabled_by_default: entity.and_then(|e| e.enabled_by_default),
+            device: None,
+            // With coordination, the other bridges take over the tags of a
+            // stopped bridge, so its availability doesn't apply
+            availability: if config.coordination.enabled {
+                Vec::new()
+            } else {
+                vec![Availability {
+                    topic: config.mqtt.availability_topic(),
+                    payload_available: "online",
+                    payload_not_available: "offline",
+                }]
+            },
+            topic: format!(
+                "{}/{}/ruuvi_{}/{}/config",
+                config.homeassistant.discovery_prefix, device_type.component, id, snake_name
+            ),
         }
-        devices
+    }
+}
+
+impl<'a> DeviceDiscovery<'a> {
+    pub fn new(
+        config: &'a config::Config,
+        bdaddr: BDAddr,
+        device: &'a config::Device,
+        data_format: Option<u8>,
+    ) -> Self {
+        let id = bdaddr.to_string_no_delim();
+        let components = DeviceType::for_device(config, device)
+            .iter()
+            .map(|device_type| {
+                let entity = Device {
+                    platform: Some(device_type.component),
+                    name: entity_name(device, device_type),
+                    ..Device::new(config, &id, device, device_type)
+                };
+                (
+                    entity.unique_id.clone(),
+                    Component::Sensor(Box::new(entity)),
+                )
+            })
+            .collect();
+
+        Self {
+            device: DeviceInfo {
+                model: device
+                    .model
+                    .clone()
+                    .or_else(|| data_format.and_then(model).map(String::from)),
+                connections: vec![("bluetooth", bdaddr.to_string())],
+                via_device: Some(config.mqtt.client_id.clone()),
+                ..DeviceInfo::new(device, bdaddr)
+            },
+            origin: Origin::new(),
+            components,
+            topic: format!(
+                "{}/device/ruuvi_{}/config",
+                config.homeassistant.discovery_prefix, id
+            ),
+        }
+    }
+
+    /// The bridge itself, with its availability as a connectivity sensor.
+    pub fn bridge(config: &config::Config) -> Self {
+        let client_id = &config.mqtt.client_id;
+        let connectivity = Connectivity {
+            platform: "binary_sensor",
+            name: "Connectivity",
+            unique_id: format!("{client_id}_connectivity"),
+            device_class: "connectivity",
+            entity_category: "diagnostic",
+            state_topic: config.mqtt.availability_topic(),
+            payload_on: "online",
+            payload_off: "offline",
+        };
+
+        Self {
+            device: DeviceInfo {
+                name: client_id.clone(),
+                identifiers: vec![client_id.clone()],
+                manufacturer: "ruuvi2mqtt",
+                model: Some(String::from("ruuvi2mqtt")),
+                hw_version: None,
+                sw_version: Some(env!("CARGO_PKG_VERSION")),
+                suggested_area: None,
+                connections: Vec::new(),
+                via_device: None,
+            },
+            origin: Origin::new(),
+            components: BTreeMap::from([(
+                connectivity.unique_id.clone(),
+                Component::Connectivity(connectivity),
+            )]),
+            topic: format!(
+                "{}/device/{}/config",
+                config.homeassistant.discovery_prefix, client_id
+            ),
+        }
+    }
+}
+
+impl Origin {
+    pub fn new() -> Self {
+        Self {
+            name: env!("CARGO_PKG_NAME"),
+            sw_version: env!("CARGO_PKG_VERSION"),
+            support_url: env!("CARGO_PKG_HOMEPAGE"),
+        }
+    }
+}
+
+/// Entity name without the device name, unless overridden in the config.
+fn entity_name(device: &config::Device, device_type: &DeviceType) -> String {
+    device
+        .entities
+        .get(&device_type.snake_name())
+        .and_then(|entity| entity.name.clone())
+        .unwrap_or_else(|| device_type.name.to_string())
+}
+
+/// Device model by the Ruuvi data format.
+fn model(data_format: u8) -> Option<&'static str> {
+    match data_format {
+        3 => Some("RuuviTag (RAWv1)"),
+        5 => Some("RuuviTag (RAWv2)"),
+        _ => None,
     }
 }
 
@@ -100,27 +407,48 @@ impl SensorData {
             topic: format!("{}/{}", base_topic, data.bdaddr.to_string_no_delim()),
             humidity: data.humidity(),
             pressure: data.pressure(),
+            sea_level_pressure: data.sea_level_pressure(),
             temperature: data.temperature(),
             battery: data.battery(),
             battery_low: data.battery_low(),
+            battery_level: data.battery_level(),
+            battery_days_remaining: None,
             tx_power: data.tx_power(),
+            movement_counter: data.movement_counter(),
+            movement: None,
+            pitch: None,
+            roll: None,
+            open: None,
+            calibrated: data.calibrated(),
+            rejected_readings: 0,
+            packet_loss: None,
+            gateway: None,
+            rssi: data.rssi,
+            alerts: BTreeMap::new(),
         }
     }
 }
 
 impl DeviceInfo<'_> {
-    pub fn new(name: String, bdaddr: BDAddr) -> Self {
+    pub fn new(device: &config::Device, bdaddr: BDAddr) -> Self {
         Self {
-            name,
+            name: device.name.clone(),
             identifiers: vec![bdaddr.to_string()],
             manufacturer: "Ruuvi",
+            model: device.model.clone(),
+            hw_version: device.hw_version.clone(),
+            sw_version: None,
+            suggested_area: device.area.clone(),
+            connections: Vec::new(),
+            via_device: None,
         }
     }
 }
 
 impl<'a> DeviceType<'a> {
+    #[allow(clippy::too_many_lines)] // table of the entities
     pub fn all() -> std::slice::Iter<'a, Self> {
-        static DEVICE_TYPES: [DeviceType<'static>; 6] = [
+        static DEVICE_TYPES: [DeviceType<'static>; 10] = [
             DeviceType {
                 component: "sensor",
                 name: "Temperature",
@@ -128,6 +456,9 @@ impl<'a> DeviceType<'a> {
                 unit_of_measurement: Some("°C"),
                 entity_category: None,
                 icon: None,
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
             },
             DeviceType {
                 component: "sensor",
@@ -136,6 +467,9 @@ impl<'a> DeviceType<'a> {
                 unit_of_measurement: Some("%"),
                 entity_category: None,
                 icon: None,
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
             },
             DeviceType {
                 component: "sensor",
@@ -144,6 +478,9 @@ impl<'a> DeviceType<'a> {
                 unit_of_measurement: Some("hPa"),
                 entity_category: None,
                 icon: None,
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
             },
             DeviceType {
                 component: "sensor",
@@ -152,6 +489,9 @@ impl<'a> DeviceType<'a> {
                 unit_of_measurement: Some("V"),
                 entity_category: Some("diagnostic"),
                 icon: Some("mdi:battery"),
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
             },
             DeviceType {
                 component: "binary_sensor",
@@ -160,6 +500,9 @@ impl<'a> DeviceType<'a> {
                 unit_of_measurement: None,
                 entity_category: Some("diagnostic"),
                 icon: None,
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
             },
             DeviceType {
                 component: "sensor",
@@ -168,10 +511,134 @@ impl<'a> DeviceType<'a> {
                 unit_of_measurement: Some("dBm"),
                 entity_category: Some("diagnostic"),
                 icon: Some("mdi:signal"),
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
+            },
+            DeviceType {
+                component: "sensor",
+                name: "Rejected Readings",
+                device_class: None,
+                unit_of_measurement: None,
+                entity_category: Some("diagnostic"),
+                icon: Some("mdi:alert-circle-outline"),
+                state_class: Some("total_increasing"),
+                off_delay: None,
+                key_prefix: None,
+            },
+            DeviceType {
+                component: "binary_sensor",
+                name: "Movement",
+                device_class: Some("vibration"),
+                unit_of_measurement: None,
+                entity_category: None,
+                icon: None,
+                state_class: None,
+                off_delay: Some(MOVEMENT_OFF_DELAY),
+                key_prefix: None,
+            },
+            DeviceType {
+                component: "sensor",
+                name: "Battery Level",
+                device_class: Some("battery"),
+                unit_of_measurement: Some("%"),
+                entity_category: Some("diagnostic"),
+                icon: None,
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
+            },
+            DeviceType {
+                component: "sensor",
+                name: "Packet Loss",
+                device_class: None,
+                unit_of_measurement: Some("%"),
+                entity_category: Some("diagnostic"),
+                icon: Some("mdi:signal-off"),
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
             },
         ];
         DEVICE_TYPES.iter()
     }
+
+    /// Device types applicable to the configured device.
+    pub fn for_device(config: &config::Config, device: &'a config::Device) -> Vec<Self> {
+        let mut device_types: Vec<Self> = Self::all().copied().collect();
+        if config.altitude(device).is_some() {
+            device_types.push(DeviceType {
+                component: "sensor",
+                name: "Sea Level Pressure",
+                device_class: Some("atmospheric_pressure"),
+                unit_of_measurement: Some("hPa"),
+                entity_category: None,
+                icon: None,
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
+            });
+        }
+        if config.battery.forecast {
+            device_types.push(DeviceType {
+                component: "sensor",
+                name: "Battery Days Remaining",
+                device_class: Some("duration"),
+                unit_of_measurement: Some("d"),
+                entity_category: Some("diagnostic"),
+                icon: Some("mdi:battery-clock"),
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
+            });
+        }
+        if let Some(orientation) = &device.orientation {
+            for name in ["Pitch", "Roll"] {
+                device_types.push(DeviceType {
+                    component: "sensor",
+                    name,
+                    device_class: None,
+                    unit_of_measurement: Some("°"),
+                    entity_category: None,
+                    icon: Some("mdi:angle-acute"),
+                    state_class: None,
+                    off_delay: None,
+                    key_prefix: None,
+                });
+            }
+            device_types.push(DeviceType {
+                component: "binary_sensor",
+                name: "Open",
+                device_class: Some(&orientation.device_class),
+                unit_of_measurement: None,
+                entity_category: None,
+                icon: None,
+                state_class: None,
+                off_delay: None,
+                key_prefix: None,
+            });
+        }
+        device_types.extend(device.alerts.iter().map(|alert| DeviceType {
+            component: "binary_sensor",
+            name: &alert.name,
+            device_class: Some(&alert.device_class),
+            unit_of_measurement: None,
+            entity_category: None,
+            icon: None,
+            state_class: None,
+            off_delay: None,
+            key_prefix: Some(config::Alert::KEY_PREFIX),
+        }));
+        device_types
+    }
+
+    fn snake_name(&self) -> String {
+        let name = config::snake_case(self.name);
+        match self.key_prefix {
+            Some(prefix) => format!("{prefix}_{name}"),
+            None => name,
+        }
+    }
 }
 
 impl PayloadInfo {
@@ -185,3 +652,177 @@ impl PayloadInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_config(homeassistant: &str) -> config::Config {
+        serde_yaml::from_str(&format!(
+            "mqtt:\n  server: localhost\n  client_id: gw1\n{homeassistant}\ndevices:\n  AA:BB:CC:DD:EE:FF:\n    name: Sauna\n"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn entity_discovery_publishes_one_message_per_entity() {
+        let config = make_config("");
+        let all = Discovery::all(&config, &HashMap::new());
+        assert_eq!(all.len(), DeviceType::all().len());
+        assert_eq!(
+            all[0].topic(),
+            "homeassistant/sensor/ruuvi_aabbccddeeff/temperature/config"
+        );
+
+        let json = serde_json::to_value(&all[0]).unwrap();
+        assert_eq!(
+            json["availability"],
+            serde_json::json!([{
+                "topic": "ruuvi2mqtt/bridge/gw1/state",
+                "payload_available": "online",
+                "payload_not_available": "offline",
+            }])
+        );
+    }
+
+    #[test]
+    fn coordinated_entities_have_no_bridge_availability() {
+        let config = make_config(
+            "coordination:
+  enabled: true",
+        );
+        let all = Discovery::all(&config, &HashMap::new());
+        let json = serde_json::to_value(&all[0]).unwrap();
+        assert!(json.get("availability").is_none());
+    }
+
+    #[test]
+    fn device_discovery_publishes_one_message_per_device_and_bridge() {
+        let config = make_config("homeassistant:\n  device_discovery: true");
+        let bdaddr: BDAddr = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let all = Discovery::all(&config, &HashMap::from([(bdaddr, 5)]));
+        let topics: Vec<&str> = all.iter().map(Discovery::topic).collect();
+        assert_eq!(
+            topics,
+            [
+                "homeassistant/device/ruuvi_aabbccddeeff/config",
+                "homeassistant/device/gw1/config"
+            ]
+        );
+
+        let json = serde_json::to_value(&all[0]).unwrap();
+        assert_eq!(json["device"]["model"], "RuuviTag (RAWv2)");
+        assert_eq!(json["device"]["via_device"], "gw1");
+        assert_eq!(
+            json["device"]["connections"],
+            serde_json::json!([["bluetooth", "AA:BB:CC:DD:EE:FF"]])
+        );
+        assert_eq!(json["origin"]["name"], "ruuvi2mqtt");
+        let components = json["components"].as_object().unwrap();
+        assert_eq!(components.len(), DeviceType::all().len());
+        let temperature = &components["ruuvi_aabbccddeeff_temperature"];
+        assert_eq!(temperature["platform"], "sensor");
+        assert_eq!(temperature["name"], "Temperature");
+        assert!(temperature.get("device").is_none());
+    }
+
+    #[test]
+    fn device_metadata_flows_into_discovery() {
+        let config: config::Config = serde_yaml::from_str(
+            "mqtt:\n  server: localhost\ndevices:\n  AA:BB:CC:DD:EE:FF:\n    name: Sauna\n    area: Bathroom\n    model: RuuviTag Pro\n    entities:\n      temperature:\n        name: Air Temperature\n        icon: mdi:thermometer\n      tx_power:\n        enabled_by_default: false\n",
+        )
+        .unwrap();
+        let all = Discovery::all(
+            &config,
+            &HashMap::from([("AA:BB:CC:DD:EE:FF".parse().unwrap(), 5)]),
+        );
+        let temperature = serde_json::to_value(&all[0]).unwrap();
+        assert_eq!(temperature["name"], "Sauna Air Temperature");
+        assert_eq!(temperature["icon"], "mdi:thermometer");
+        assert_eq!(temperature["device"]["suggested_area"], "Bathroom");
+        assert_eq!(temperature["device"]["model"], "RuuviTag Pro");
+        assert!(temperature.get("enabled_by_default").is_none());
+
+        let tx_power = serde_json::to_value(&all[5]).unwrap();
+        assert_eq!(tx_power["icon"], "mdi:signal");
+        assert_eq!(tx_power["enabled_by_default"], false);
+    }
+
+    #[test]
+    fn sea_level_pressure_is_announced_with_altitude() {
+        let config = make_config("altitude: 120");
+        let all = Discovery::all(&config, &HashMap::new());
+        assert_eq!(all.len(), DeviceType::all().len() + 1);
+        assert_eq!(
+            all.last().unwrap().topic(),
+            "homeassistant/sensor/ruuvi_aabbccddeeff/sea_level_pressure/config"
+        );
+    }
+
+    #[test]
+    fn alerts_are_namespaced() {
+        let config: config::Config = serde_yaml::from_str(
+            "mqtt:\n  server: localhost\ndevices:\n  AA:BB:CC:DD:EE:FF:\n    name: Sauna\n    alerts:\n      - name: Battery (Low)\n        condition: battery < 2.5\n",
+        )
+        .unwrap();
+        let all = Discovery::all(&config, &HashMap::new());
+        let alert = serde_json::to_value(all.last().unwrap()).unwrap();
+        assert_eq!(
+            all.last().unwrap().topic(),
+            "homeassistant/binary_sensor/ruuvi_aabbccddeeff/alert_battery_low/config"
+        );
+        assert_eq!(alert["name"], "Sauna Battery (Low)");
+        assert_eq!(alert["unique_id"], "ruuvi_aabbccddeeff_alert_battery_low");
+        assert_eq!(
+            alert["value_template"],
+            "{{ value_json.alert_battery_low }}"
+        );
+    }
+
+    #[test]
+    fn uncalibrated_open_state_is_published_as_null() {
+        let values = ruuvi_sensor_protocol::SensorValues::from_manufacturer_specific_data(
+            0x0499,
+            [
+                5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+        )
+        .unwrap();
+        let sensor = ruuvi::SensorData::new(BDAddr::from([0u8; 6]), 5, values);
+        let mut data = SensorData::new(&sensor, "ruuvi2mqtt");
+        assert!(serde_json::to_value(&data).unwrap().get("open").is_none());
+
+        data.open = Some(None);
+        assert!(serde_json::to_value(&data).unwrap()["open"].is_null());
+    }
+
+    #[test]
+    fn gateway_and_rssi_are_published_if_known() {
+        let values = ruuvi_sensor_protocol::SensorValues::from_manufacturer_specific_data(
+            0x0499,
+            [
+                5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+            ],
+        )
+        .unwrap();
+        let mut sensor = ruuvi::SensorData::new(BDAddr::from([0u8; 6]), 5, values);
+        let json = serde_json::to_value(SensorData::new(&sensor, "ruuvi2mqtt")).unwrap();
+        assert!(json.get("gateway").is_none());
+        assert!(json.get("rssi").is_none());
+
+        sensor.rssi = Some(-70);
+        let mut data = SensorData::new(&sensor, "ruuvi2mqtt");
+        data.gateway = Some("gw1".into());
+        let json = serde_json::to_value(&data).unwrap();
+        assert_eq!(json["gateway"], "gw1");
+        assert_eq!(json["rssi"], -70);
+    }
+
+    #[test]
+    fn device_discovery_without_data_format_has_no_model() {
+        let config = make_config("homeassistant:\n  device_discovery: true");
+        let all = Discovery::all(&config, &HashMap::new());
+        let json = serde_json::to_value(&all[0]).unwrap();
+        assert!(json["device"].get("model").is_none());
+    }
+}
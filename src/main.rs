@@ -1,25 +1,55 @@
+mod alerts;
+mod battery;
+mod bridge;
+mod check;
+mod coerce;
+mod command;
 mod config;
+mod coordination;
 mod devices;
 mod homeassistant;
 mod mqtt;
+mod orientation;
+mod registry;
 mod ruuvi;
+mod scan;
+mod sequence;
+mod validation;
+
+use std::collections::HashMap;
+use std::time::Duration;
 
 use anyhow::Result;
+use tokio::signal::unix::{SignalKind, signal};
 use tokio::sync::mpsc;
 
-use crate::config::{CliOptions, Config};
-use crate::devices::{Devices, ThrottleResult};
-use crate::homeassistant::SensorData;
+use crate::bridge::Bridge;
+use crate::config::{CliCommand, CliOptions, Config};
+use crate::coordination::Observation;
 use crate::mqtt::Mqtt;
-use crate::ruuvi::{BDAddr, RuuviListener};
+use crate::ruuvi::capture::Recorder;
+use crate::ruuvi::{AdvertisementSource, BDAddr, Replay, RuuviListener, Synthetic};
 
 type EventSender = mpsc::Sender<crate::Event>;
 
+/// How long to wait for pending MQTT messages on shutdown
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Debug)]
 pub enum Event {
     RuuviUpdate(ruuvi::SensorData),
     MqttDeviceUpdate(BDAddr),
+    MqttCalibrateClosed(BDAddr),
+    /// RSSI report of a gateway (client ID)
+    MqttRssiReport(String, HashMap<BDAddr, Observation>),
+    /// Payload of a runtime command
+    MqttCommand(Vec<u8>),
+    /// Discovery topics published by a gateway (client ID)
+    MqttDiscoveryManifest(String, Vec<String>),
     MqttConnect,
+    HomeAssistantOnline,
+    /// No more advertisements, e.g. the replay has ended
+    SourceFinished,
 }
 
 #[tokio::main]
@@ -28,69 +58,113 @@ async fn main() -> Result<()> {
 
     init_logger(options.log_level);
 
+    match options.command {
+        Some(CliCommand::CheckConfig) => return check::check_config(&options),
+        Some(CliCommand::Scan { duration, yaml }) => {
+            return scan::scan(&options, duration.into(), yaml).await;
+        }
+        None => {}
+    }
+
     log::info!("{}", config::version_info().trim_end());
     log::debug!("{options:?}");
 
     let config = Config::load(&options)?;
     log::debug!("{config:?}");
 
-    let mut devices = Devices::new(&config.devices, config.mqtt.throttle);
-
     let (tx, mut rx) = mpsc::channel(32);
-    let mut mqtt = Mqtt::init(tx.clone(), &config.mqtt)?;
-    RuuviListener::new(tx, config.mqtt.throttle / 100)
-        .await?
-        .start()
-        .await?;
+    let mqtt = if options.dry_run {
+        Mqtt::dry_run(tx.clone(), &config)?
+    } else {
+        Mqtt::init(tx.clone(), &config)?
+    };
+    let mut source: Box<dyn AdvertisementSource> = if let Some(file) = options.replay.clone() {
+        Box::new(Replay::new(tx, file))
+    } else if let Some(interval) = options.simulate {
+        let tags = config.devices.keys().copied().collect();
+        Box::new(Synthetic::new(tx, tags, interval.into()))
+    } else {
+        let recorder = options
+            .record
+            .as_deref()
+            .map(Recorder::create)
+            .transpose()?;
+        // The coordination elects a single bridge for each tag instead
+        let sleep =
+            (!config.coordination.enabled).then(|| RuuviListener::jitter(config.mqtt.throttle));
+        Box::new(RuuviListener::new(tx, sleep, recorder).await?)
+    };
+    source.start().await?;
 
-    while let Some(event) = rx.recv().await {
-        use Event::{MqttConnect, MqttDeviceUpdate, RuuviUpdate};
+    let mut report_interval = config
+        .coordination
+        .enabled
+        .then(|| tokio::time::interval(config.coordination.interval));
+    let mut bridge = Bridge::new(config, mqtt);
 
-        log::trace!("Received event: {event:?}");
-        match event {
-            MqttConnect => {
-                log::info!("Connected to Mqtt. Publishing devices.");
-                for device in homeassistant::Device::all(&config) {
-                    mqtt.publish_device(device);
-                }
+    let mut sighup = signal(SignalKind::hangup())?;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        let event = tokio::select! {
+            event = rx.recv() => event,
+            _ = async { report_interval.as_mut().unwrap().tick().await }, if report_interval.is_some() => {
+                bridge.report_rssi();
+                continue;
             }
-            MqttDeviceUpdate(bdaddr) => {
-                if let Some(device) = devices.mark_published(&bdaddr) {
-                    log::debug!("Updated from Mqtt: '{}' [{}]", device.name, bdaddr);
+            _ = sighup.recv() => {
+                log::info!("Received SIGHUP. Reloading configuration.");
+                if let Err(err) = bridge.reload_config(&options) {
+                    log::error!("Failed to reload configuration: {err:?}");
                 }
+                continue;
             }
-            RuuviUpdate(sensor) => {
-                let device_name = devices.get(&sensor.bdaddr).map(|d| d.name.as_str());
-                match devices.should_publish(&sensor.bdaddr) {
-                    ThrottleResult::UnknownDevice => {
-                        log::debug!("Unknown device: [{}]", sensor.bdaddr);
-                    }
-                    ThrottleResult::Throttle => {
-                        log::debug!(
-                            "Throttled: '{}' [{}]",
-                            device_name.unwrap_or("?"),
-                            sensor.bdaddr,
-                        );
-                    }
-                    ThrottleResult::Update => {
-                        log::info!(
-                            "Updating: '{}' [{}]",
-                            device_name.unwrap_or("?"),
-                            sensor.bdaddr,
-                        );
-                        let data = SensorData::new(&sensor, &config.mqtt.base_topic);
-                        mqtt.publish_sensor_data(data);
-                    }
-                }
+            result = &mut shutdown => {
+                result?;
+                break;
             }
+        };
+        let Some(event) = event else { break };
+        if let Event::SourceFinished = event {
+            log::info!("No more advertisements");
+            break;
         }
+
+        log::trace!("Received event: {event:?}");
+        bridge.handle_event(event);
+    }
+
+    log::info!("Shutting down...");
+    if let Err(err) = source.stop().await {
+        log::error!("Failed to stop the advertisement source: {err:?}");
+    }
+    bridge.shutdown(SHUTDOWN_TIMEOUT).await;
+    Ok(())
+}
+
+/// Resolves when the process receives SIGTERM or SIGINT.
+async fn shutdown_signal() -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = sigterm.recv() => log::info!("Received SIGTERM"),
+        _ = sigint.recv() => log::info!("Received SIGINT"),
     }
     Ok(())
 }
 
 fn init_logger(log_level: log::LevelFilter) {
-    env_logger::Builder::new()
-        .filter_level(log_level)
-        .parse_default_env()
-        .init();
+    if std::env::var_os("RUST_LOG").is_some() {
+        env_logger::Builder::new()
+            .filter_level(log_level)
+            .parse_default_env()
+            .init();
+    } else {
+        // Filter only by the max level, so that it can be changed at runtime
+        env_logger::Builder::new()
+            .filter_level(log::LevelFilter::Trace)
+            .init();
+        log::set_max_level(log_level);
+    }
 }
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 use std::sync::Arc;
@@ -12,18 +13,46 @@ use rumqttc::tokio_rustls::rustls::{
 };
 use rumqttc::{
     AsyncClient, ConnectReturnCode, Event as MqttEvent, EventLoop as MqttEventLoop, Incoming,
-    MqttOptions, QoS, TlsConfiguration, Transport,
+    LastWill, MqttOptions, Outgoing, QoS, TlsConfiguration, Transport,
 };
-use tokio::time::sleep;
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{sleep, timeout};
 
-use crate::Event::{self, MqttConnect, MqttDeviceUpdate};
+use crate::Event::{
+    self, HomeAssistantOnline, MqttCalibrateClosed, MqttCommand, MqttConnect, MqttDeviceUpdate,
+    MqttDiscoveryManifest, MqttRssiReport,
+};
 use crate::EventSender;
+use crate::command::Response;
 use crate::config;
-use crate::homeassistant::{Device, SensorData};
+use crate::coordination::Observation;
+use crate::homeassistant::{Discovery, SensorData};
 use crate::ruuvi::BDAddr;
 
+const PAYLOAD_ONLINE: &str = "online";
+const PAYLOAD_OFFLINE: &str = "offline";
+
 pub struct Mqtt {
-    client: AsyncClient,
+    output: Output,
+    availability_topic: String,
+    discovery_manifest_topic: String,
+    rssi_topic: String,
+    response_topic: String,
+    tasks: JoinSet<()>,
+}
+
+/// Where the messages are published
+enum Output {
+    Broker {
+        client: AsyncClient,
+        eventloop_task: JoinHandle<()>,
+    },
+    /// Dry run: JSON lines on stdout
+    Stdout {
+        /// For echoing the device states like the subscription does
+        tx: EventSender,
+        state_topic_prefix: String,
+    },
 }
 
 #[derive(Clone)]
@@ -31,26 +60,59 @@ pub struct EventLoop {
     tx: EventSender,
     client: AsyncClient,
     state_topic_prefix: String,
+    command_topic: String,
+    homeassistant_status_topic: String,
 }
 
 impl Mqtt {
-    pub fn init(tx: EventSender, config: &config::Mqtt) -> Result<Self> {
-        let (client, eventloop) = AsyncClient::new(Self::options(config)?, 10);
-
-        let state_topic_prefix = format!("{}/", config.base_topic);
-        let event_loop_client = client.clone();
-        tokio::spawn(async move {
-            EventLoop::new(tx, event_loop_client, state_topic_prefix)
-                .run(eventloop)
-                .await;
+    pub fn init(tx: EventSender, config: &config::Config) -> Result<Self> {
+        let (client, eventloop) = AsyncClient::new(Self::options(&config.mqtt)?, 10);
+
+        let handler = EventLoop::new(tx, client.clone(), config);
+        let eventloop_task = tokio::spawn(async move {
+            handler.run(eventloop).await;
         });
 
-        Ok(Self { client })
+        Ok(Self::new(
+            Output::Broker {
+                client,
+                eventloop_task,
+            },
+            config,
+        ))
+    }
+
+    /// Writes the messages to stdout instead of connecting to the broker.
+    pub fn dry_run(tx: EventSender, config: &config::Config) -> Result<Self> {
+        // Publish the devices as if connected
+        tx.try_send(MqttConnect).context("Failed to send event")?;
+        let output = Output::Stdout {
+            tx,
+            state_topic_prefix: format!("{}/", config.mqtt.base_topic),
+        };
+        Ok(Self::new(output, config))
+    }
+
+    fn new(output: Output, config: &config::Config) -> Self {
+        Self {
+            output,
+            availability_topic: config.mqtt.availability_topic(),
+            discovery_manifest_topic: config.mqtt.discovery_manifest_topic(),
+            rssi_topic: config.mqtt.rssi_topic(),
+            response_topic: config.mqtt.response_topic(),
+            tasks: JoinSet::new(),
+        }
     }
 
     fn options(config: &config::Mqtt) -> Result<MqttOptions> {
         let mut options = MqttOptions::new(&config.client_id, &config.server, config.port());
         options.set_keep_alive(Duration::from_secs(15));
+        options.set_last_will(LastWill::new(
+            config.availability_topic(),
+            PAYLOAD_OFFLINE,
+            QoS::AtLeastOnce,
+            true,
+        ));
         if let Some(user) = &config.user {
             let password = config
                 .password
@@ -104,35 +166,150 @@ impl Mqtt {
         Ok(options)
     }
 
-    pub fn publish_device(&mut self, device: Device<'static>) {
-        let client = self.client.clone();
-        tokio::spawn(async move {
-            log::debug!("Publishing: {} -> {:?}", device.topic, device);
-            let payload = serde_json::to_vec(&device).unwrap();
-            match client
-                .publish(device.topic, QoS::AtLeastOnce, true, payload)
-                .await
-            {
-                Ok(()) => log::trace!("OK!"),
-                Err(err) => log::error!("Failed to publish: {err}"),
-            }
-        });
+    pub fn publish_availability(&mut self, online: bool) {
+        let topic = self.availability_topic.clone();
+        let payload = if online {
+            PAYLOAD_ONLINE
+        } else {
+            PAYLOAD_OFFLINE
+        };
+        log::debug!("Publishing: {topic} -> {payload}");
+        self.publish(topic, QoS::AtLeastOnce, true, payload.into());
+    }
+
+    pub fn publish_device(&mut self, device: &Discovery) {
+        log::debug!("Publishing: {} -> {:?}", device.topic(), device);
+        let topic = device.topic().to_string();
+        let payload = serde_json::to_vec(device).unwrap();
+        self.publish(topic, QoS::AtLeastOnce, true, payload);
+    }
+
+    pub fn publish_discovery_manifest(&mut self, topics: &[String]) {
+        let topic = self.discovery_manifest_topic.clone();
+        log::debug!("Publishing: {topic} -> {topics:?}");
+        let payload = serde_json::to_vec(&topics).unwrap();
+        self.publish(topic, QoS::AtLeastOnce, true, payload);
+    }
+
+    pub fn publish_rssi_report(&mut self, report: &HashMap<BDAddr, Observation>) {
+        let topic = self.rssi_topic.clone();
+        let report: HashMap<String, Observation> = report
+            .iter()
+            .map(|(bdaddr, observation)| (bdaddr.to_string_no_delim(), *observation))
+            .collect();
+        log::debug!("Publishing: {topic} -> {report:?}");
+        let payload = serde_json::to_vec(&report).unwrap();
+        self.publish(topic, QoS::AtMostOnce, false, payload);
+    }
+
+    pub fn publish_response(&mut self, response: &Response) {
+        let topic = self.response_topic.clone();
+        log::debug!("Publishing: {topic} -> {response:?}");
+        let payload = serde_json::to_vec(response).unwrap();
+        self.publish(topic, QoS::AtLeastOnce, false, payload);
+    }
+
+    /// Clears a retained discovery config, removing the entity from Home Assistant.
+    pub fn remove_device(&mut self, topic: String) {
+        log::debug!("Removing: {topic}");
+        self.publish(topic, QoS::AtLeastOnce, true, Vec::new());
     }
 
     pub fn publish_sensor_data(&mut self, data: SensorData) {
-        let client = self.client.clone();
-        tokio::spawn(async move {
-            log::debug!("Publishing: {} -> {:?}", data.topic, data);
-            let payload = serde_json::to_vec(&data).unwrap();
-            match client
-                .publish(data.topic, QoS::AtLeastOnce, false, payload)
+        log::debug!("Publishing: {} -> {:?}", data.topic, data);
+        let payload = serde_json::to_vec(&data).unwrap();
+        self.publish(data.topic, QoS::AtLeastOnce, false, payload);
+    }
+
+    fn publish(&mut self, topic: String, qos: QoS, retain: bool, payload: Vec<u8>) {
+        match &self.output {
+            Output::Broker { client, .. } => {
+                let client = client.clone();
+                self.spawn(async move {
+                    match client.publish(topic, qos, retain, payload).await {
+                        Ok(()) => log::trace!("OK!"),
+                        Err(err) => log::error!("Failed to publish: {err}"),
+                    }
+                });
+            }
+            Output::Stdout {
+                tx,
+                state_topic_prefix,
+            } => {
+                println!("{}", json_line(&topic, retain, &payload));
+                // Mark the device published, so that it's throttled
+                if let Some(bdaddr) = topic
+                    .strip_prefix(state_topic_prefix.as_str())
+                    .and_then(|id| BDAddr::from_str_no_delim(id).ok())
+                {
+                    let tx = tx.clone();
+                    self.spawn(async move {
+                        let _ = tx.send(MqttDeviceUpdate(bdaddr)).await;
+                    });
+                }
+            }
+        }
+    }
+
+    /// Flushes pending publishes, marks the bridge offline, and disconnects
+    /// from the broker. Gives up after `limit`.
+    pub async fn shutdown(mut self, limit: Duration) {
+        let Output::Broker {
+            client,
+            mut eventloop_task,
+        } = self.output
+        else {
+            println!(
+                "{}",
+                json_line(&self.availability_topic, true, PAYLOAD_OFFLINE.as_bytes())
+            );
+            return;
+        };
+        let result = timeout(limit, async {
+            while self.tasks.join_next().await.is_some() {}
+            if let Err(err) = client
+                .publish(
+                    &self.availability_topic,
+                    QoS::AtLeastOnce,
+                    true,
+                    PAYLOAD_OFFLINE,
+                )
                 .await
             {
-                Ok(()) => log::trace!("OK!"),
-                Err(err) => log::error!("Failed to publish: {err}"),
+                log::error!("Failed to publish: {err}");
             }
-        });
+            if let Err(err) = client.disconnect().await {
+                log::error!("Failed to disconnect: {err}");
+            }
+            if let Err(err) = (&mut eventloop_task).await {
+                log::error!("MQTT eventloop failed: {err}");
+            }
+        })
+        .await;
+
+        if result.is_err() {
+            log::warn!("Timed out waiting for MQTT to disconnect");
+            eventloop_task.abort();
+        }
     }
+
+    fn spawn<F>(&mut self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // Reap finished publishes so that only the pending ones are tracked
+        while self.tasks.try_join_next().is_some() {}
+        self.tasks.spawn(task);
+    }
+}
+
+/// Formats a message as a JSON line. Payloads that aren't JSON are written as
+/// strings.
+fn json_line(topic: &str, retain: bool, payload: &[u8]) -> String {
+    let payload = serde_json::from_slice(payload).unwrap_or_else(|_| {
+        serde_json::Value::String(String::from_utf8_lossy(payload).into_owned())
+    });
+    serde_json::json!({ "topic": topic, "retain": retain, "payload": payload }).to_string()
 }
 
 #[derive(Debug)]
@@ -186,11 +363,13 @@ impl ServerCertVerifier for NoVerifier {
 }
 
 impl EventLoop {
-    pub fn new(tx: EventSender, client: AsyncClient, state_topic_prefix: String) -> Self {
+    pub fn new(tx: EventSender, client: AsyncClient, config: &config::Config) -> Self {
         Self {
             tx,
             client,
-            state_topic_prefix,
+            state_topic_prefix: format!("{}/", config.mqtt.base_topic),
+            command_topic: config.mqtt.command_topic(),
+            homeassistant_status_topic: config.homeassistant.status_topic.clone(),
         }
     }
 
@@ -198,6 +377,15 @@ impl EventLoop {
         log::info!("Starting MQTT evenloop");
         loop {
             match eventloop.poll().await {
+                Ok(MqttEvent::Outgoing(Outgoing::Disconnect)) => {
+                    log::info!("Disconnected from MQTT");
+                    break;
+                }
+                // Handled in order, e.g. the retained discovery manifests
+                Ok(event @ MqttEvent::Incoming(Incoming::Publish(_))) => {
+                    log::trace!("Event: {event:?}");
+                    self.clone().on_event(event).await;
+                }
                 Ok(event) => {
                     let e = self.clone();
                     tokio::spawn(async move {
@@ -218,25 +406,115 @@ impl EventLoop {
             MqttEvent::Incoming(Incoming::ConnAck(conn))
                 if conn.code == ConnectReturnCode::Success =>
             {
-                let topic = format!("{}#", self.state_topic_prefix);
-                log::debug!("Subscribing to: {topic}");
-                if let Err(err) = self.client.subscribe(&topic, QoS::AtMostOnce).await {
-                    log::error!("Failed to subscribe: {err}");
+                let state_topic = format!("{}#", self.state_topic_prefix);
+                for topic in [&state_topic, &self.homeassistant_status_topic] {
+                    log::debug!("Subscribing to: {topic}");
+                    if let Err(err) = self.client.subscribe(topic, QoS::AtMostOnce).await {
+                        log::error!("Failed to subscribe: {err}");
+                    }
                 }
                 self.send_event(MqttConnect).await;
             }
+            MqttEvent::Incoming(Incoming::Publish(msg))
+                if msg.topic == self.homeassistant_status_topic
+                    && msg.payload.as_ref() == b"online" =>
+            {
+                self.send_event(HomeAssistantOnline).await;
+            }
+            MqttEvent::Incoming(Incoming::Publish(msg))
+                if msg.topic == self.command_topic && msg.retain =>
+            {
+                // Would be executed again on every reconnect
+                log::warn!("Ignoring retained command: {}", msg.topic);
+            }
+            MqttEvent::Incoming(Incoming::Publish(msg)) if msg.topic == self.command_topic => {
+                self.send_event(MqttCommand(msg.payload.to_vec())).await;
+            }
             MqttEvent::Incoming(Incoming::Publish(msg)) => {
-                if let Some(suffix) = msg.topic.strip_prefix(&self.state_topic_prefix)
-                    && let Ok(bdaddr) = BDAddr::from_str_no_delim(suffix)
-                {
-                    self.send_event(MqttDeviceUpdate(bdaddr)).await;
+                let Some(suffix) = msg.topic.strip_prefix(&self.state_topic_prefix) else {
+                    return;
+                };
+                match suffix.split_once('/') {
+                    None => {
+                        if let Ok(bdaddr) = BDAddr::from_str_no_delim(suffix) {
+                            self.send_event(MqttDeviceUpdate(bdaddr)).await;
+                        }
+                    }
+                    Some((id, "calibrate_closed")) => match BDAddr::from_str_no_delim(id) {
+                        Ok(bdaddr) => self.send_event(MqttCalibrateClosed(bdaddr)).await,
+                        Err(err) => log::warn!("Invalid calibration topic {}: {err}", msg.topic),
+                    },
+                    Some(("bridge", rest)) => {
+                        if let Some(gateway) = rest.strip_suffix("/rssi") {
+                            self.on_rssi_report(gateway, &msg.payload).await;
+                        } else if let Some(gateway) = rest.strip_suffix("/discovery") {
+                            self.on_discovery_manifest(gateway, &msg.payload).await;
+                        }
+                    }
+                    Some(_) => {}
                 }
             }
             _ => {}
         }
     }
 
+    async fn on_rssi_report(&self, gateway: &str, payload: &[u8]) {
+        let report: HashMap<String, Observation> = match serde_json::from_slice(payload) {
+            Ok(report) => report,
+            Err(err) => {
+                log::warn!("Invalid RSSI report from '{gateway}': {err}");
+                return;
+            }
+        };
+        let report = report
+            .into_iter()
+            .filter_map(|(id, observation)| {
+                Some((BDAddr::from_str_no_delim(&id).ok()?, observation))
+            })
+            .collect();
+        self.send_event(MqttRssiReport(gateway.to_string(), report))
+            .await;
+    }
+
+    async fn on_discovery_manifest(&self, gateway: &str, payload: &[u8]) {
+        // An empty payload means that the manifest has been cleared
+        let topics = if payload.is_empty() {
+            Vec::new()
+        } else {
+            match serde_json::from_slice(payload) {
+                Ok(topics) => topics,
+                Err(err) => {
+                    log::warn!("Invalid discovery manifest from '{gateway}': {err}");
+                    return;
+                }
+            }
+        };
+        self.send_event(MqttDiscoveryManifest(gateway.to_string(), topics))
+            .await;
+    }
+
     async fn send_event(&self, event: Event) {
         self.tx.send(event).await.expect("Failed to send event");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_json_lines() {
+        assert_eq!(
+            json_line("ruuvi2mqtt/aabbccddeeff", false, br#"{"temperature":21.5}"#),
+            r#"{"payload":{"temperature":21.5},"retain":false,"topic":"ruuvi2mqtt/aabbccddeeff"}"#
+        );
+        assert_eq!(
+            json_line("ruuvi2mqtt/bridge/host/state", true, b"online"),
+            r#"{"payload":"online","retain":true,"topic":"ruuvi2mqtt/bridge/host/state"}"#
+        );
+        assert_eq!(
+            json_line("homeassistant/sensor/x/config", true, b""),
+            r#"{"payload":"","retain":true,"topic":"homeassistant/sensor/x/config"}"#
+        );
+    }
+}
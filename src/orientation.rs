@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+
+use crate::config;
+use crate::ruuvi::BDAddr;
+
+/// Tracks the orientation of the tags for the open/closed state.
+#[derive(Default)]
+pub struct OrientationTracker {
+    /// Closed positions calibrated at runtime, override the configuration
+    closed: HashMap<BDAddr, [f32; 3]>,
+    /// Last seen acceleration vector of each tag
+    last: HashMap<BDAddr, [f32; 3]>,
+    /// Last open state of each tag
+    open: HashMap<BDAddr, bool>,
+}
+
+impl OrientationTracker {
+    /// Records the acceleration and returns the open state, if the closed
+    /// position is known.
+    pub fn update(
+        &mut self,
+        bdaddr: BDAddr,
+        config: &config::Orientation,
+        acceleration: [f32; 3],
+    ) -> Option<bool> {
+        self.last.insert(bdaddr, acceleration);
+        let closed = self.closed.get(&bdaddr).copied().or(config.closed)?;
+        let open = angle(closed, acceleration) > config.threshold;
+        self.open.insert(bdaddr, open);
+        Some(open)
+    }
+
+    pub fn is_open(&self, bdaddr: BDAddr) -> Option<bool> {
+        self.open.get(&bdaddr).copied()
+    }
+
+    /// Uses the last seen orientation of the tag as the closed position.
+    pub fn calibrate_closed(&mut self, bdaddr: BDAddr) -> Option<[f32; 3]> {
+        let acceleration = *self.last.get(&bdaddr)?;
+        self.closed.insert(bdaddr, acceleration);
+        Some(acceleration)
+    }
+}
+
+/// Rotation around the Y axis (degrees)
+pub fn pitch([x, y, z]: [f32; 3]) -> f32 {
+    (-x).atan2(y.hypot(z)).to_degrees()
+}
+
+/// Rotation around the X axis (degrees)
+pub fn roll([_, y, z]: [f32; 3]) -> f32 {
+    y.atan2(z).to_degrees()
+}
+
+/// Angle between two vectors (degrees)
+fn angle(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let dot: f32 = a.iter().zip(&b).map(|(a, b)| a * b).sum();
+    let length = |v: [f32; 3]| v.iter().map(|c| c * c).sum::<f32>().sqrt();
+    (dot / (length(a) * length(b)))
+        .clamp(-1.0, 1.0)
+        .acos()
+        .to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_config(closed: Option<[f32; 3]>) -> config::Orientation {
+        config::Orientation {
+            device_class: "garage_door".into(),
+            closed,
+            threshold: 30.0,
+        }
+    }
+
+    #[test]
+    fn pitch_and_roll() {
+        assert!(pitch([0.0, 0.0, 1.0]).abs() < 0.01);
+        assert!(roll([0.0, 0.0, 1.0]).abs() < 0.01);
+        assert!((pitch([-1.0, 0.0, 0.0]) - 90.0).abs() < 0.01);
+        assert!((roll([0.0, 1.0, 0.0]) - 90.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn open_state_from_tilt() {
+        let bdaddr = BDAddr::from([0u8; 6]);
+        let config = make_config(Some([0.0, 0.0, 1.0]));
+        let mut tracker = OrientationTracker::default();
+        assert_eq!(
+            tracker.update(bdaddr, &config, [0.0, 0.3, 0.95]),
+            Some(false)
+        );
+        assert_eq!(tracker.update(bdaddr, &config, [0.0, 1.0, 0.0]), Some(true));
+    }
+
+    #[test]
+    fn calibrates_closed_position() {
+        let bdaddr = BDAddr::from([0u8; 6]);
+        let config = make_config(None);
+        let mut tracker = OrientationTracker::default();
+        assert_eq!(tracker.calibrate_closed(bdaddr), None);
+        assert_eq!(tracker.update(bdaddr, &config, [0.0, 1.0, 0.0]), None);
+        assert_eq!(tracker.calibrate_closed(bdaddr), Some([0.0, 1.0, 0.0]));
+        assert_eq!(
+            tracker.update(bdaddr, &config, [0.0, 1.0, 0.0]),
+            Some(false)
+        );
+        assert_eq!(tracker.update(bdaddr, &config, [0.0, 0.0, 1.0]), Some(true));
+    }
+}
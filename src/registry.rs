@@ -0,0 +1,115 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_with::{DisplayFromStr, serde_as};
+
+use crate::config;
+use crate::ruuvi::BDAddr;
+
+/// Devices added or renamed at runtime, persisted in a writable YAML file.
+#[serde_as]
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Registry {
+    #[serde_as(as = "BTreeMap<DisplayFromStr, _>")]
+    devices: BTreeMap<BDAddr, Entry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Entry {
+    pub name: String,
+}
+
+impl Registry {
+    /// Reads the registry. A missing file is an empty registry.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(registry) => serde_yaml::from_str(&registry)
+                .with_context(|| format!("Failed to load {}", path.display())),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Writes the registry atomically by replacing the file.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        write_atomically(path, &serde_yaml::to_string(self).unwrap())
+    }
+
+    /// Adds or renames a device.
+    pub fn set_name(&mut self, bdaddr: BDAddr, name: &str) {
+        self.devices.insert(
+            bdaddr,
+            Entry {
+                name: name.to_string(),
+            },
+        );
+    }
+
+    /// Adds the registered devices to the configured ones, renaming the
+    /// existing devices.
+    pub fn apply(self, devices: &mut HashMap<BDAddr, config::Device>) {
+        for (bdaddr, entry) in self.devices {
+            devices.entry(bdaddr).or_default().name = entry.name;
+        }
+    }
+}
+
+/// Writes the file atomically by replacing it.
+pub fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let write = || -> std::io::Result<()> {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    };
+    write().with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_and_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("devices.yaml");
+        let bdaddr: BDAddr = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+
+        let mut registry = Registry::load(&path).unwrap();
+        registry.set_name(bdaddr, "Garage");
+        registry.save(&path).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "AA:BB:CC:DD:EE:FF:\n  name: Garage\n"
+        );
+
+        let mut devices = HashMap::new();
+        Registry::load(&path).unwrap().apply(&mut devices);
+        assert_eq!(devices[&bdaddr].name, "Garage");
+    }
+
+    #[test]
+    fn renames_configured_devices() {
+        let bdaddr: BDAddr = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let mut devices = HashMap::from([(
+            bdaddr,
+            config::Device {
+                name: "Sauna".into(),
+                area: Some("Bathroom".into()),
+                ..Default::default()
+            },
+        )]);
+        let mut registry = Registry::default();
+        registry.set_name(bdaddr, "Steam Room");
+        registry.apply(&mut devices);
+        assert_eq!(devices[&bdaddr].name, "Steam Room");
+        assert_eq!(devices[&bdaddr].area.as_deref(), Some("Bathroom"));
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result};
+use futures::future::{BoxFuture, FutureExt};
+use ruuvi_sensor_protocol::{MacAddress, SensorValues};
+use serde::{Deserialize, Serialize};
+use serde_with::hex::Hex;
+use serde_with::{DisplayFromStr, serde_as};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, sleep_until};
+
+use crate::Event::{RuuviUpdate, SourceFinished};
+use crate::EventSender;
+use crate::ruuvi::{AdvertisementSource, BDAddr, SensorData};
+
+/// Bluetooth manufacturer ID of Ruuvi Innovations
+pub const RUUVI_MANUFACTURER_ID: u16 = 0x0499;
+
+/// Raw advertisement, stored as one JSON line in a capture file
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Advertisement {
+    #[serde_as(as = "DisplayFromStr")]
+    pub timestamp: humantime::Timestamp,
+    /// Address of the BLE peripheral
+    #[serde_as(as = "DisplayFromStr")]
+    pub mac: BDAddr,
+    pub manufacturer_id: u16,
+    /// Manufacturer specific data
+    #[serde_as(as = "Hex")]
+    pub data: Vec<u8>,
+    pub rssi: Option<i16>,
+}
+
+impl Advertisement {
+    /// Parses the sensor data. Returns `None` if it isn't a Ruuvi advertisement.
+    pub fn sensor_data(&self) -> Option<SensorData> {
+        let values =
+            SensorValues::from_manufacturer_specific_data(self.manufacturer_id, &self.data).ok()?;
+        // Data format 3 doesn't contain the MAC address
+        let bdaddr = values.mac_address().map_or(self.mac, BDAddr::from);
+        let mut data = SensorData::new(bdaddr, self.data[0], values);
+        data.rssi = self.rssi;
+        Some(data)
+    }
+}
+
+/// Writes the advertisements to a capture file.
+pub struct Recorder {
+    file: Mutex<LineWriter<File>>,
+}
+
+impl Recorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create capture file: {}", path.display()))?;
+        log::info!("Recording advertisements to {}", path.display());
+        Ok(Self {
+            file: Mutex::new(LineWriter::new(file)),
+        })
+    }
+
+    pub fn record(&self, advertisement: &Advertisement) -> Result<()> {
+        let line = serde_json::to_string(advertisement)?;
+        writeln!(self.file.lock().unwrap(), "{line}")?;
+        Ok(())
+    }
+}
+
+/// Reads the advertisements of a capture file.
+pub fn read(path: &Path) -> Result<Vec<Advertisement>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open capture file: {}", path.display()))?;
+    let mut advertisements = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let advertisement = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: Invalid advertisement", path.display(), index + 1))?;
+        advertisements.push(advertisement);
+    }
+    Ok(advertisements)
+}
+
+/// Replays a capture file instead of scanning.
+pub struct Replay {
+    path: PathBuf,
+    tx: EventSender,
+    task: Option<JoinHandle<()>>,
+}
+
+impl Replay {
+    pub fn new(tx: EventSender, path: PathBuf) -> Self {
+        Self {
+            path,
+            tx,
+            task: None,
+        }
+    }
+}
+
+impl AdvertisementSource for Replay {
+    fn start(&mut self) -> BoxFuture<'_, Result<()>> {
+        let path = self.path.clone();
+        let tx = self.tx.clone();
+        self.task = Some(tokio::spawn(async move {
+            if let Err(err) = replay(&path, &tx).await {
+                log::error!("Failed to replay advertisements: {err:?}");
+            }
+            let _ = tx.send(SourceFinished).await;
+        }));
+        async { Ok(()) }.boxed()
+    }
+
+    fn stop(&mut self) -> BoxFuture<'_, Result<()>> {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        async { Ok(()) }.boxed()
+    }
+}
+
+/// Sends the advertisements of a capture file as sensor updates, keeping the
+/// recorded intervals.
+async fn replay(path: &Path, tx: &EventSender) -> Result<()> {
+    let advertisements = read(path)?;
+    log::info!(
+        "Replaying {} advertisements from {}",
+        advertisements.len(),
+        path.display()
+    );
+
+    let start = Instant::now();
+    let first = advertisements
+        .first()
+        .map_or(SystemTime::UNIX_EPOCH, |a| *a.timestamp);
+    for advertisement in advertisements {
+        let offset = advertisement
+            .timestamp
+            .duration_since(first)
+            .unwrap_or_default();
+        sleep_until(start + offset).await;
+        if let Some(data) = advertisement.sensor_data() {
+            tx.send(RuuviUpdate(data)).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = r#"{"timestamp":"2026-10-19T12:00:00Z","mac":"CB:B8:33:4C:88:4F","manufacturer_id":1177,"data":"0512fc5394c37c0004fffc040cac364200cdcbb8334c884f","rssi":-72}"#;
+
+    #[test]
+    fn serializes_advertisement() {
+        let advertisement: Advertisement = serde_json::from_str(LINE).unwrap();
+        assert_eq!(advertisement.manufacturer_id, RUUVI_MANUFACTURER_ID);
+        assert_eq!(advertisement.data.len(), 24);
+        assert_eq!(serde_json::to_string(&advertisement).unwrap(), LINE);
+    }
+
+    #[test]
+    fn parses_sensor_data() {
+        let mut advertisement: Advertisement = serde_json::from_str(LINE).unwrap();
+        let data = advertisement.sensor_data().unwrap();
+        assert_eq!(data.bdaddr.to_string(), "CB:B8:33:4C:88:4F");
+        assert_eq!(data.data_format, 5);
+        assert_eq!(data.rssi, Some(-72));
+        assert_eq!(data.temperature(), Some(24.3));
+
+        advertisement.manufacturer_id = 0x004c;
+        assert!(advertisement.sensor_data().is_none());
+    }
+
+    #[test]
+    fn uses_advertisement_address_without_mac_in_data() {
+        let advertisement = Advertisement {
+            timestamp: SystemTime::UNIX_EPOCH.into(),
+            mac: "CB:B8:33:4C:88:4F".parse().unwrap(),
+            manufacturer_id: RUUVI_MANUFACTURER_ID,
+            // Data format 3
+            data: vec![
+                0x03, 0x29, 0x1a, 0x1e, 0xce, 0x1e, 0xfc, 0x18, 0xf9, 0x42, 0x02, 0xca, 0x0b, 0x53,
+            ],
+            rssi: None,
+        };
+        let data = advertisement.sensor_data().unwrap();
+        assert_eq!(data.bdaddr.to_string(), "CB:B8:33:4C:88:4F");
+        assert_eq!(data.data_format, 3);
+    }
+}
@@ -1,24 +1,34 @@
+use std::sync::Arc;
+use std::time::SystemTime;
+
 use anyhow::{Context, Result};
 use btleplug::api::{Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter};
 use btleplug::platform::{Adapter, Manager, Peripheral, PeripheralId};
+use futures::future::{BoxFuture, FutureExt};
 use futures::stream::StreamExt;
 use rand::RngExt;
-use ruuvi_sensor_protocol::{MacAddress, SensorValues};
 use tokio::time::{Duration, sleep};
 
 use crate::Event::RuuviUpdate;
 use crate::EventSender;
-use crate::ruuvi::SensorData;
+use crate::ruuvi::AdvertisementSource;
+use crate::ruuvi::capture::{Advertisement, RUUVI_MANUFACTURER_ID, Recorder};
 
 #[derive(Clone)]
 pub struct RuuviListener {
     central: Adapter,
     tx: EventSender,
-    sleep: Duration,
+    /// Delay before forwarding each advertisement
+    sleep: Option<Duration>,
+    recorder: Option<Arc<Recorder>>,
 }
 
 impl RuuviListener {
-    pub async fn new(tx: EventSender, sleep: Duration) -> Result<Self> {
+    pub async fn new(
+        tx: EventSender,
+        sleep: Option<Duration>,
+        recorder: Option<Recorder>,
+    ) -> Result<Self> {
         let manager = Manager::new().await?;
 
         // get the first bluetooth adapter
@@ -28,15 +38,26 @@ impl RuuviListener {
             .next()
             .context("No Bluetooth adapters found")?;
 
-        let sleep = if sleep.is_zero() {
+        Ok(Self {
+            central,
+            tx,
+            sleep,
+            recorder: recorder.map(Arc::new),
+        })
+    }
+
+    /// Delay spreading the updates of multiple bridges: 1 % of the throttle,
+    /// or random up to 500 ms without throttling.
+    pub fn jitter(throttle: Duration) -> Duration {
+        let sleep = throttle / 100;
+        if sleep.is_zero() {
             Duration::from_millis(rand::rng().random_range(0..500))
         } else {
             sleep
-        };
-        Ok(Self { central, tx, sleep })
+        }
     }
 
-    pub async fn start(self) -> Result<()> {
+    pub async fn start(&self) -> Result<()> {
         let mut events = self.central.events().await?;
 
         log::info!(
@@ -46,9 +67,10 @@ impl RuuviListener {
         self.central.start_scan(ScanFilter::default()).await?;
 
         // Start BLE event loop
+        let listener = self.clone();
         tokio::spawn(async move {
             while let Some(event) = events.next().await {
-                let ruuvi = self.clone();
+                let ruuvi = listener.clone();
                 tokio::spawn(async move {
                     log::trace!("BLE event: {event:?}");
                     if let Err(err) = ruuvi.on_event(event).await {
@@ -61,6 +83,12 @@ impl RuuviListener {
         Ok(())
     }
 
+    pub async fn stop(&self) -> Result<()> {
+        log::info!("Stopping BLE scan...");
+        self.central.stop_scan().await?;
+        Ok(())
+    }
+
     async fn on_event(self, event: CentralEvent) -> Result<()> {
         match event {
             CentralEvent::DeviceDiscovered(id)
@@ -68,15 +96,16 @@ impl RuuviListener {
             | CentralEvent::RssiUpdate { id, .. } => {
                 let peripheral = self.find_peripheral(&id).await?;
                 log::trace!("BLE Peripheral: {peripheral:?}");
-                if let Some(values) = Self::parse_data(&peripheral).await? {
-                    log::trace!("Ruuvi event: {values:?}");
-                    let address = values
-                        .mac_address()
-                        .context(format!("BDAddr not found: {peripheral:?}"))?;
-                    let data = SensorData::new(address.into(), values);
-                    // Sleep a bit to avoid multiple/simultaneus updates
-                    sleep(self.sleep).await;
-                    self.tx.send(RuuviUpdate(data)).await?;
+                for advertisement in Self::advertisements(&peripheral).await? {
+                    self.record(&advertisement);
+                    if let Some(data) = advertisement.sensor_data() {
+                        log::trace!("Ruuvi event: {data:?}");
+                        // Sleep a bit to avoid multiple/simultaneus updates
+                        if let Some(duration) = self.sleep {
+                            sleep(duration).await;
+                        }
+                        self.tx.send(RuuviUpdate(data)).await?;
+                    }
                 }
             }
             _ => {}
@@ -91,13 +120,41 @@ impl RuuviListener {
             .context("Failed to find peripheral")
     }
 
-    async fn parse_data(peripheral: &Peripheral) -> Result<Option<SensorValues>> {
-        Ok(peripheral
-            .properties()
-            .await?
-            .unwrap()
+    /// Returns the Ruuvi manufacturer data of the peripheral
+    async fn advertisements(peripheral: &Peripheral) -> Result<Vec<Advertisement>> {
+        let Some(properties) = peripheral.properties().await? else {
+            return Ok(Vec::new());
+        };
+        let timestamp = SystemTime::now().into();
+        Ok(properties
             .manufacturer_data
             .into_iter()
-            .find_map(|(id, data)| SensorValues::from_manufacturer_specific_data(id, data).ok()))
+            .filter(|(id, _)| *id == RUUVI_MANUFACTURER_ID)
+            .map(|(manufacturer_id, data)| Advertisement {
+                timestamp,
+                mac: properties.address,
+                manufacturer_id,
+                data,
+                rssi: properties.rssi,
+            })
+            .collect())
+    }
+
+    fn record(&self, advertisement: &Advertisement) {
+        if let Some(recorder) = &self.recorder
+            && let Err(err) = recorder.record(advertisement)
+        {
+            log::error!("Failed to record advertisement: {err:?}");
+        }
+    }
+}
+
+impl AdvertisementSource for RuuviListener {
+    fn start(&mut self) -> BoxFuture<'_, Result<()>> {
+        RuuviListener::start(self).boxed()
+    }
+
+    fn stop(&mut self) -> BoxFuture<'_, Result<()>> {
+        RuuviListener::stop(self).boxed()
     }
 }
@@ -1,7 +1,13 @@
+pub mod capture;
 mod listener;
 mod sensor_data;
+mod source;
+mod synthetic;
 
+pub use capture::Replay;
 pub use listener::RuuviListener;
 pub use sensor_data::SensorData;
+pub use source::AdvertisementSource;
+pub use synthetic::Synthetic;
 
 pub type BDAddr = btleplug::api::BDAddr;
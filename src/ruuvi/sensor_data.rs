@@ -1,34 +1,104 @@
 use ruuvi_sensor_protocol::{
-    BatteryPotential, Humidity, Pressure, SensorValues, Temperature, TransmitterPower,
+    Acceleration, AccelerationVector, BatteryPotential, Humidity, MeasurementSequenceNumber,
+    MovementCounter, Pressure, SensorValues, Temperature, TransmitterPower,
 };
 
+use crate::config::{Adjustment, Calibration};
 use crate::ruuvi::BDAddr;
 
 #[derive(Debug)]
 pub struct SensorData {
     pub bdaddr: BDAddr,
+    pub data_format: u8,
+    /// Signal strength (dBm) seen by this gateway
+    pub rssi: Option<i16>,
     values: SensorValues,
+    calibration: Calibration,
+    altitude: Option<f32>,
 }
 
 impl SensorData {
-    pub const fn new(bdaddr: BDAddr, values: SensorValues) -> Self {
-        Self { bdaddr, values }
+    pub const fn new(bdaddr: BDAddr, data_format: u8, values: SensorValues) -> Self {
+        Self {
+            bdaddr,
+            data_format,
+            rssi: None,
+            values,
+            calibration: Calibration {
+                temperature: None,
+                humidity: None,
+                pressure: None,
+            },
+            altitude: None,
+        }
+    }
+
+    /// Sets the calibration applied to the measurement values.
+    pub fn calibrate(&mut self, calibration: &Calibration) {
+        self.calibration = calibration.clone();
+    }
+
+    /// Sets the altitude (meters) used for the sea-level pressure.
+    pub const fn set_altitude(&mut self, altitude: Option<f32>) {
+        self.altitude = altitude;
+    }
+
+    /// Names of the calibrated values
+    pub fn calibrated(&self) -> Vec<&'static str> {
+        [
+            ("temperature", self.calibration.temperature),
+            ("humidity", self.calibration.humidity),
+            ("pressure", self.calibration.pressure),
+        ]
+        .into_iter()
+        .filter_map(|(name, adjustment)| adjustment.map(|_| name))
+        .collect()
     }
 
-    #[allow(clippy::cast_precision_loss)] // sensor value ranges are well within f32 precision
     pub fn temperature(&self) -> Option<f32> {
+        adjust(self.raw_temperature(), self.calibration.temperature)
+    }
+
+    pub fn humidity(&self) -> Option<f32> {
+        let humidity = adjust(self.raw_humidity(), self.calibration.humidity);
+        // The calibration may push the values slightly out of the range
+        if self.calibration.humidity.is_some() {
+            humidity.map(|v| v.clamp(0.0, 100.0))
+        } else {
+            humidity
+        }
+    }
+
+    pub fn pressure(&self) -> Option<f32> {
+        adjust(self.raw_pressure(), self.calibration.pressure)
+    }
+
+    /// Pressure reduced to sea level using the tag's own temperature.
+    pub fn sea_level_pressure(&self) -> Option<f32> {
+        let altitude = self.altitude?;
+        let pressure = self.pressure()?;
+        let temperature = self.temperature()?;
+
+        // Barometric formula, as used by weather services
+        let lapse = 0.0065 * altitude;
+        Some(pressure * (1.0 - lapse / (temperature + lapse + 273.15)).powf(-5.257))
+    }
+
+    #[allow(clippy::cast_precision_loss)] // sensor value ranges are well within f32 precision
+    fn raw_temperature(&self) -> Option<f32> {
         self.values
             .temperature_as_millicelsius()
             .map(|v| v as f32 / 1000.0)
     }
 
+    /// Humidity before the calibration, for validating the reading.
     #[allow(clippy::cast_precision_loss)]
-    pub fn humidity(&self) -> Option<f32> {
+    pub fn raw_humidity(&self) -> Option<f32> {
         self.values.humidity_as_ppm().map(|v| v as f32 / 10000.0)
     }
 
     #[allow(clippy::cast_precision_loss)]
-    pub fn pressure(&self) -> Option<f32> {
+    fn raw_pressure(&self) -> Option<f32> {
         self.values.pressure_as_pascals().map(|v| v as f32 / 100.0)
     }
 
@@ -50,9 +120,67 @@ impl SensorData {
         )
     }
 
+    /// Estimated state of charge (%) from the CR2477 discharge curve.
+    ///
+    /// The voltage sags in the cold, so it's compensated by 10 mV per degree
+    /// below 0 °C, up to 0.5 V.
+    pub fn battery_level(&self) -> Option<f32> {
+        let battery = self.battery()?;
+        let compensation = self
+            .temperature()
+            .map_or(0.0, |t| (-t * 0.01).clamp(0.0, 0.5));
+        Some(discharge_curve(battery + compensation))
+    }
+
     pub fn tx_power(&self) -> Option<i8> {
         self.values.tx_power_as_dbm()
     }
+
+    /// Acceleration vector (g)
+    pub fn acceleration(&self) -> Option<[f32; 3]> {
+        self.values
+            .acceleration_vector_as_milli_g()
+            .map(|AccelerationVector(x, y, z)| [x, y, z].map(|v| f32::from(v) / 1000.0))
+    }
+
+    pub fn measurement_sequence_number(&self) -> Option<u32> {
+        self.values.measurement_sequence_number()
+    }
+
+    pub fn movement_counter(&self) -> Option<u32> {
+        self.values.movement_counter()
+    }
+}
+
+/// CR2477 voltage to remaining capacity (%), interpolated between the points
+fn discharge_curve(voltage: f32) -> f32 {
+    const CURVE: [(f32, f32); 9] = [
+        (2.0, 0.0),
+        (2.2, 3.0),
+        (2.4, 8.0),
+        (2.5, 15.0),
+        (2.6, 25.0),
+        (2.7, 40.0),
+        (2.8, 60.0),
+        (2.9, 80.0),
+        (3.0, 100.0),
+    ];
+    match CURVE.iter().position(|&(v, _)| voltage < v) {
+        Some(0) => 0.0,
+        Some(i) => {
+            let (v0, l0) = CURVE[i - 1];
+            let (v1, l1) = CURVE[i];
+            l0 + (voltage - v0) / (v1 - v0) * (l1 - l0)
+        }
+        None => 100.0,
+    }
+}
+
+fn adjust(value: Option<f32>, adjustment: Option<Adjustment>) -> Option<f32> {
+    match adjustment {
+        Some(adjustment) => value.map(|v| adjustment.apply(v)),
+        None => value,
+    }
 }
 
 #[cfg(test)]
@@ -60,6 +188,7 @@ mod tests {
     use ruuvi_sensor_protocol::SensorValues;
 
     use super::SensorData;
+    use crate::config::{Adjustment, Calibration};
     use crate::ruuvi::BDAddr;
 
     /// Builds a `SensorData` from optional temperature (millicelsius) and battery (millivolts)
@@ -84,7 +213,7 @@ mod tests {
             5, t1, t2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, pow1, pow2, 0, 0, 0, 0, 0, 0, 0, 0, 0,
         ];
         let values = SensorValues::from_manufacturer_specific_data(0x0499, payload).unwrap();
-        SensorData::new(BDAddr::from([0u8; 6]), values)
+        SensorData::new(BDAddr::from([0u8; 6]), 5, values)
     }
 
     #[test]
@@ -163,4 +292,109 @@ mod tests {
         let s = make_sensor(Some(0), Some(2499));
         assert_eq!(s.battery_low(), Some(true));
     }
+
+    // --- Battery level ---
+
+    #[test]
+    fn battery_level_follows_discharge_curve() {
+        let level = |mv| make_sensor(Some(20_000), Some(mv)).battery_level().unwrap();
+        assert!((level(3100) - 100.0).abs() < 0.01);
+        assert!((level(2850) - 70.0).abs() < 0.01);
+        assert!((level(2500) - 15.0).abs() < 0.01);
+        assert!(level(1900).abs() < 0.01);
+    }
+
+    #[test]
+    fn battery_level_is_compensated_in_the_cold() {
+        // 2.3 V at -20 °C is estimated as 2.5 V at room temperature
+        let s = make_sensor(Some(-20_000), Some(2300));
+        assert!((s.battery_level().unwrap() - 15.0).abs() < 0.01);
+    }
+
+    // --- Sea-level pressure ---
+
+    /// Builds a v5 `SensorData` with temperature (millicelsius) and pressure (pascals).
+    fn make_pressure_sensor(temp_mc: i32, pressure_pa: u32) -> SensorData {
+        #[allow(clippy::cast_possible_truncation)] // test values are always within range
+        let [t1, t2] = ((temp_mc / 5) as i16).to_be_bytes();
+        // Pressure: raw_u16 = Pa - 50000
+        #[allow(clippy::cast_possible_truncation)]
+        let [p1, p2] = ((pressure_pa - 50_000) as u16).to_be_bytes();
+        #[rustfmt::skip]
+        let payload = [
+            5, t1, t2, 0, 0, p1, p2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let values = SensorValues::from_manufacturer_specific_data(0x0499, payload).unwrap();
+        SensorData::new(BDAddr::from([0u8; 6]), 5, values)
+    }
+
+    #[test]
+    fn sea_level_pressure_none_without_altitude() {
+        let s = make_pressure_sensor(15_000, 100_000);
+        assert_eq!(s.sea_level_pressure(), None);
+    }
+
+    #[test]
+    fn sea_level_pressure_at_zero_altitude_equals_pressure() {
+        let mut s = make_pressure_sensor(15_000, 100_000);
+        s.set_altitude(Some(0.0));
+        assert_eq!(s.sea_level_pressure(), Some(1000.0));
+    }
+
+    #[test]
+    fn sea_level_pressure_is_higher_above_sea_level() {
+        let mut s = make_pressure_sensor(15_000, 100_000);
+        s.set_altitude(Some(100.0));
+        let pressure = s.sea_level_pressure().unwrap();
+        assert!((pressure - 1011.9).abs() < 0.1, "{pressure}");
+    }
+
+    // --- Calibration ---
+
+    #[test]
+    fn calibration_adjusts_temperature() {
+        let mut s = make_sensor(Some(20_000), Some(3000));
+        s.calibrate(&Calibration {
+            temperature: Some(Adjustment {
+                gain: 1.0,
+                offset: -0.4,
+            }),
+            ..Calibration::default()
+        });
+        assert!((s.temperature().unwrap() - 19.6).abs() < 0.001);
+        assert_eq!(s.calibrated(), ["temperature"]);
+    }
+
+    #[test]
+    fn calibrated_humidity_is_clamped() {
+        let mut s = make_sensor(Some(20_000), Some(3000));
+        s.calibrate(&Calibration {
+            humidity: Some(Adjustment {
+                gain: 1.0,
+                offset: -3.0,
+            }),
+            ..Calibration::default()
+        });
+        // Humidity is 0 % in the test packet
+        assert_eq!(s.humidity(), Some(0.0));
+    }
+
+    #[test]
+    fn uncalibrated_humidity_is_not_clamped() {
+        // 163.0 % (raw 65200) in an otherwise empty v5 packet
+        let [h1, h2] = 65200_u16.to_be_bytes();
+        #[rustfmt::skip]
+        let payload = [
+            5, 0, 0, h1, h2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let values = SensorValues::from_manufacturer_specific_data(0x0499, payload).unwrap();
+        let s = SensorData::new(BDAddr::from([0u8; 6]), 5, values);
+        assert_eq!(s.humidity(), Some(163.0));
+    }
+
+    #[test]
+    fn uncalibrated_values_are_not_flagged() {
+        let s = make_sensor(Some(20_000), Some(3000));
+        assert!(s.calibrated().is_empty());
+    }
 }
@@ -0,0 +1,12 @@
+use anyhow::Result;
+use futures::future::BoxFuture;
+
+/// Source of the sensor updates, e.g. the Bluetooth scan
+///
+/// The sources send `Event::RuuviUpdate`s to the channel given to their
+/// constructor, and `Event::SourceFinished` if they run out of advertisements.
+pub trait AdvertisementSource: Send {
+    fn start(&mut self) -> BoxFuture<'_, Result<()>>;
+
+    fn stop(&mut self) -> BoxFuture<'_, Result<()>>;
+}
@@ -0,0 +1,154 @@
+use std::time::{Duration, SystemTime};
+
+use anyhow::Result;
+use futures::future::{BoxFuture, FutureExt};
+use rand::RngExt;
+use tokio::task::JoinHandle;
+use tokio::time::interval;
+
+use crate::Event::RuuviUpdate;
+use crate::EventSender;
+use crate::ruuvi::capture::{Advertisement, RUUVI_MANUFACTURER_ID};
+use crate::ruuvi::{AdvertisementSource, BDAddr};
+
+/// Generates random-walk readings for the given tags.
+pub struct Synthetic {
+    tx: EventSender,
+    tags: Vec<BDAddr>,
+    interval: Duration,
+    task: Option<JoinHandle<()>>,
+}
+
+/// State of a generated tag
+struct Tag {
+    bdaddr: BDAddr,
+    temperature: f32,
+    humidity: f32,
+    /// hPa
+    pressure: f32,
+    sequence: u16,
+}
+
+impl Synthetic {
+    pub fn new(tx: EventSender, tags: Vec<BDAddr>, interval: Duration) -> Self {
+        Self {
+            tx,
+            tags,
+            interval,
+            task: None,
+        }
+    }
+}
+
+impl AdvertisementSource for Synthetic {
+    fn start(&mut self) -> BoxFuture<'_, Result<()>> {
+        log::info!(
+            "Generating readings for {} tags every {:?}",
+            self.tags.len(),
+            self.interval
+        );
+        let tx = self.tx.clone();
+        let mut tags: Vec<_> = self.tags.iter().map(|bdaddr| Tag::new(*bdaddr)).collect();
+        let mut interval = interval(self.interval);
+        self.task = Some(tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+                for tag in &mut tags {
+                    let advertisement = tag.next();
+                    if let Some(data) = advertisement.sensor_data()
+                        && tx.send(RuuviUpdate(data)).await.is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        }));
+        async { Ok(()) }.boxed()
+    }
+
+    fn stop(&mut self) -> BoxFuture<'_, Result<()>> {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        async { Ok(()) }.boxed()
+    }
+}
+
+impl Tag {
+    const fn new(bdaddr: BDAddr) -> Self {
+        Self {
+            bdaddr,
+            temperature: 21.0,
+            humidity: 45.0,
+            pressure: 1013.0,
+            sequence: 0,
+        }
+    }
+
+    /// Advances the values and returns the advertisement.
+    fn next(&mut self) -> Advertisement {
+        let mut rng = rand::rng();
+        self.temperature = (self.temperature + rng.random_range(-0.1..=0.1)).clamp(-40.0, 85.0);
+        self.humidity = (self.humidity + rng.random_range(-0.5..=0.5)).clamp(0.0, 100.0);
+        self.pressure = (self.pressure + rng.random_range(-0.2..=0.2)).clamp(500.0, 1100.0);
+        self.sequence = self.sequence.wrapping_add(1);
+
+        Advertisement {
+            timestamp: SystemTime::now().into(),
+            mac: self.bdaddr,
+            manufacturer_id: RUUVI_MANUFACTURER_ID,
+            data: self.raw_v2(),
+            rssi: Some(rng.random_range(-90..=-60)),
+        }
+    }
+
+    /// Encodes the values in data format 5 (a.k.a. `RAWv2`).
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn raw_v2(&self) -> Vec<u8> {
+        const BATTERY_MV: u16 = 2900;
+        // 4 dBm in 2 dBm steps from -40 dBm
+        const TX_POWER: u16 = 22;
+
+        let mut data = vec![5];
+        data.extend(((self.temperature / 0.005).round() as i16).to_be_bytes());
+        data.extend(((self.humidity / 0.0025).round() as u16).to_be_bytes());
+        data.extend(((self.pressure * 100.0 - 50000.0).round() as u16).to_be_bytes());
+        // Lying flat: x, y, z in mG
+        for acceleration in [0_i16, 0, 1000] {
+            data.extend(acceleration.to_be_bytes());
+        }
+        let power = ((BATTERY_MV - 1600) << 5) | TX_POWER;
+        data.extend(power.to_be_bytes());
+        // Movement counter
+        data.push(0);
+        data.extend(self.sequence.to_be_bytes());
+        data.extend(self.bdaddr.into_inner());
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_raw_v2_advertisements() {
+        let bdaddr = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let mut tag = Tag::new(bdaddr);
+        let data = tag.next().sensor_data().unwrap();
+
+        assert_eq!(data.bdaddr, bdaddr);
+        assert_eq!(data.data_format, 5);
+        assert!((data.temperature().unwrap() - 21.0).abs() <= 0.11);
+        assert!((data.humidity().unwrap() - 45.0).abs() <= 0.51);
+        assert!((data.pressure().unwrap() - 1013.0).abs() <= 0.21);
+        assert_eq!(data.battery(), Some(2.9));
+        assert_eq!(data.tx_power(), Some(4));
+        assert_eq!(data.acceleration(), Some([0.0, 0.0, 1.0]));
+        assert_eq!(data.measurement_sequence_number(), Some(1));
+        assert!(data.rssi.is_some());
+
+        let data = tag.next().sensor_data().unwrap();
+        assert_eq!(data.measurement_sequence_number(), Some(2));
+    }
+}
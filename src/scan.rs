@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::IsTerminal;
+use std::time::Duration;
+
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tokio::time::{Instant, interval, sleep_until};
+
+use crate::Event;
+use crate::config::{CliOptions, Config};
+use crate::ruuvi::{BDAddr, RuuviListener, SensorData};
+
+/// How often the live table is redrawn
+const REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Latest reading of a seen tag
+struct Tag {
+    data_format: u8,
+    rssi: Option<i16>,
+    temperature: Option<f32>,
+    battery: Option<f32>,
+}
+
+/// Lists the nearby tags, without connecting to MQTT.
+pub async fn scan(options: &CliOptions, duration: Duration, yaml: bool) -> Result<()> {
+    // The configuration is only used to show which tags are configured
+    let config = Config::load(options)
+        .inspect_err(|err| log::warn!("Failed to load the configuration: {err:#}"))
+        .ok();
+
+    let (tx, mut rx) = mpsc::channel(32);
+    let listener = RuuviListener::new(tx, None, None).await?;
+    listener.start().await?;
+
+    let live = std::io::stdout().is_terminal();
+    let deadline = Instant::now() + duration;
+    let mut refresh = interval(REFRESH_INTERVAL);
+    let mut tags = BTreeMap::new();
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Some(Event::RuuviUpdate(sensor)) => {
+                    tags.insert(sensor.bdaddr, Tag::new(&sensor));
+                }
+                Some(_) => {}
+                None => break,
+            },
+            _ = refresh.tick(), if live => {
+                // Clear the screen
+                print!("\x1b[2J\x1b[H{}", format_table(&tags, config.as_ref()));
+            }
+            () = sleep_until(deadline) => break,
+        }
+    }
+    listener.stop().await?;
+
+    if live {
+        print!("\x1b[2J\x1b[H");
+    }
+    print!("{}", format_table(&tags, config.as_ref()));
+    if yaml {
+        print!("\n{}", yaml_snippet(&tags, config.as_ref()));
+    }
+    Ok(())
+}
+
+impl Tag {
+    fn new(sensor: &SensorData) -> Self {
+        Self {
+            data_format: sensor.data_format,
+            rssi: sensor.rssi,
+            temperature: sensor.temperature(),
+            battery: sensor.battery(),
+        }
+    }
+}
+
+fn configured_name(config: Option<&Config>, bdaddr: BDAddr) -> Option<&str> {
+    config?
+        .devices
+        .get(&bdaddr)
+        .map(|device| device.name.as_str())
+}
+
+/// Table of the tags, the strongest signal first.
+fn format_table(tags: &BTreeMap<BDAddr, Tag>, config: Option<&Config>) -> String {
+    let mut rows: Vec<_> = tags.iter().collect();
+    rows.sort_by_key(|(_, tag)| std::cmp::Reverse(tag.rssi));
+
+    let mut table = format!(
+        "{:<17}  {:>6}  {:>9}  {:>8}  {:>8}  {}\n",
+        "MAC", "FORMAT", "RSSI", "TEMP", "BATTERY", "CONFIGURED"
+    );
+    for (bdaddr, tag) in rows {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        writeln!(
+            table,
+            "{:<17}  {:>6}  {:>9}  {:>8}  {:>8}  {}",
+            bdaddr.to_string(),
+            tag.data_format,
+            optional(tag.rssi.map(|rssi| format!("{rssi} dBm"))),
+            optional(tag.temperature.map(|t| format!("{t:.1} °C"))),
+            optional(tag.battery.map(|b| format!("{b:.3} V"))),
+            configured_name(config, *bdaddr).unwrap_or("-"),
+        )
+        .unwrap();
+    }
+    table
+}
+
+/// `devices` section for the tags that aren't configured yet.
+fn yaml_snippet(tags: &BTreeMap<BDAddr, Tag>, config: Option<&Config>) -> String {
+    let mut yaml = String::from("devices:\n");
+    for bdaddr in tags
+        .keys()
+        .filter(|bdaddr| configured_name(config, **bdaddr).is_none())
+    {
+        let id = bdaddr.to_string_no_delim();
+        let suffix = &id[id.len() - 4..];
+        writeln!(
+            yaml,
+            "  {bdaddr}:\n    name: Ruuvi {}",
+            suffix.to_uppercase()
+        )
+        .unwrap();
+    }
+    yaml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_tags() -> BTreeMap<BDAddr, Tag> {
+        BTreeMap::from([
+            (
+                "AA:BB:CC:DD:EE:01".parse().unwrap(),
+                Tag {
+                    data_format: 5,
+                    rssi: Some(-80),
+                    temperature: Some(21.456),
+                    battery: Some(2.9),
+                },
+            ),
+            (
+                "AA:BB:CC:DD:EE:02".parse().unwrap(),
+                Tag {
+                    data_format: 3,
+                    rssi: Some(-60),
+                    temperature: None,
+                    battery: None,
+                },
+            ),
+        ])
+    }
+
+    fn make_config() -> Config {
+        serde_yaml::from_str(
+            "mqtt:\n  server: localhost\ndevices:\n  AA:BB:CC:DD:EE:01:\n    name: Sauna\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn formats_table() {
+        let config = make_config();
+        assert_eq!(
+            format_table(&make_tags(), Some(&config)),
+            "MAC                FORMAT       RSSI      TEMP   BATTERY  CONFIGURED\n\
+             AA:BB:CC:DD:EE:02       3    -60 dBm         -         -  -\n\
+             AA:BB:CC:DD:EE:01       5    -80 dBm   21.5 °C   2.900 V  Sauna\n"
+        );
+    }
+
+    #[test]
+    fn yaml_snippet_lists_unconfigured_tags() {
+        let config = make_config();
+        assert_eq!(
+            yaml_snippet(&make_tags(), Some(&config)),
+            "devices:\n  AA:BB:CC:DD:EE:02:\n    name: Ruuvi EE02\n"
+        );
+        assert!(yaml_snippet(&make_tags(), None).contains("AA:BB:CC:DD:EE:01"));
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::ruuvi::BDAddr;
+
+/// Number of sequence numbers, 0xFFFF is reserved for "not available"
+const SEQUENCE_NUMBERS: u32 = 0xFFFF;
+/// Number of measurements the packet loss is computed over
+const LOSS_WINDOW: u32 = 100;
+/// Larger gaps are considered restarts of the tag
+const MAX_GAP: u32 = 1000;
+
+/// Tracks the measurement sequence numbers of the tags, for dropping repeated
+/// advertisements and computing the packet loss.
+#[derive(Default)]
+pub struct SequenceTracker {
+    tags: HashMap<BDAddr, Tag>,
+}
+
+#[derive(Default)]
+struct Tag {
+    last: Option<u32>,
+    /// Sequence number increments between the received measurements
+    gaps: VecDeque<u32>,
+}
+
+impl SequenceTracker {
+    /// Records the sequence number. Returns false if the measurement has
+    /// already been received.
+    pub fn update(&mut self, bdaddr: BDAddr, sequence_number: u32) -> bool {
+        let tag = self.tags.entry(bdaddr).or_default();
+        let Some(last) = tag.last.replace(sequence_number) else {
+            return true;
+        };
+        let gap = (sequence_number + SEQUENCE_NUMBERS - last) % SEQUENCE_NUMBERS;
+        match gap {
+            0 => return false,
+            gap if gap > MAX_GAP => tag.gaps.clear(),
+            gap => {
+                tag.gaps.push_back(gap);
+                while tag.gaps.iter().skip(1).sum::<u32>() >= LOSS_WINDOW {
+                    tag.gaps.pop_front();
+                }
+            }
+        }
+        true
+    }
+
+    /// Percentage of missed measurements in the window
+    pub fn packet_loss(&self, bdaddr: BDAddr) -> Option<f32> {
+        let tag = self.tags.get(&bdaddr)?;
+        let expected: u32 = tag.gaps.iter().sum();
+        if expected == 0 {
+            return None;
+        }
+        #[allow(clippy::cast_possible_truncation)] // at most MAX_GAP gaps
+        let received = tag.gaps.len() as u32;
+        #[allow(clippy::cast_precision_loss)] // small numbers
+        Some((expected - received) as f32 / expected as f32 * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_duplicates() {
+        let bdaddr = BDAddr::from([0u8; 6]);
+        let mut tracker = SequenceTracker::default();
+        assert!(tracker.update(bdaddr, 1));
+        assert!(!tracker.update(bdaddr, 1));
+        assert!(tracker.update(bdaddr, 2));
+        assert_eq!(tracker.packet_loss(bdaddr), Some(0.0));
+    }
+
+    #[test]
+    fn computes_packet_loss() {
+        let bdaddr = BDAddr::from([0u8; 6]);
+        let mut tracker = SequenceTracker::default();
+        assert_eq!(tracker.packet_loss(bdaddr), None);
+        for sequence_number in [0, 1, 3, 4] {
+            tracker.update(bdaddr, sequence_number);
+        }
+        // 1 of 4 measurements missed
+        assert_eq!(tracker.packet_loss(bdaddr), Some(25.0));
+    }
+
+    #[test]
+    fn loss_window_is_limited() {
+        let bdaddr = BDAddr::from([0u8; 6]);
+        let mut tracker = SequenceTracker::default();
+        tracker.update(bdaddr, 0);
+        tracker.update(bdaddr, 10);
+        for sequence_number in 11..=200 {
+            tracker.update(bdaddr, sequence_number);
+        }
+        assert_eq!(tracker.packet_loss(bdaddr), Some(0.0));
+    }
+
+    #[test]
+    fn handles_wraparound_and_restarts() {
+        let bdaddr = BDAddr::from([0u8; 6]);
+        let mut tracker = SequenceTracker::default();
+        tracker.update(bdaddr, 0xFFFE);
+        assert!(tracker.update(bdaddr, 0));
+        assert_eq!(tracker.packet_loss(bdaddr), Some(0.0));
+        // Restarted tag
+        tracker.update(bdaddr, 5000);
+        assert_eq!(tracker.packet_loss(bdaddr), None);
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::config;
+use crate::ruuvi::{BDAddr, SensorData};
+
+/// Validated quantities: name, and the minimum standard deviation used for
+/// spike detection, so that stable readings don't reject every change.
+const QUANTITIES: [(&str, f32); 3] = [("temperature", 0.1), ("humidity", 0.5), ("pressure", 0.1)];
+
+/// Rejects implausible readings, e.g. from corrupted advertisements.
+pub struct Validator {
+    config: config::Validation,
+    history: HashMap<BDAddr, [VecDeque<f32>; 3]>,
+    rejected: HashMap<BDAddr, u64>,
+}
+
+impl Validator {
+    pub fn new(config: config::Validation) -> Self {
+        Self {
+            config,
+            history: HashMap::new(),
+            rejected: HashMap::new(),
+        }
+    }
+
+    /// Returns the reason if the reading should be rejected.
+    pub fn validate(&mut self, sensor: &SensorData) -> Result<(), String> {
+        let values = [
+            sensor.temperature(),
+            // The calibration may push the plausible values out of the range
+            sensor.raw_humidity(),
+            sensor.pressure(),
+        ];
+        let result = self.check(sensor.bdaddr, values);
+        if result.is_err() {
+            *self.rejected.entry(sensor.bdaddr).or_default() += 1;
+        }
+        result
+    }
+
+    /// Number of rejected readings of the device since the start.
+    pub fn rejected(&self, bdaddr: BDAddr) -> u64 {
+        self.rejected.get(&bdaddr).copied().unwrap_or_default()
+    }
+
+    fn check(&mut self, bdaddr: BDAddr, values: [Option<f32>; 3]) -> Result<(), String> {
+        let ranges = [
+            self.config.temperature,
+            self.config.humidity,
+            self.config.pressure,
+        ];
+        for (((name, _), range), value) in QUANTITIES.iter().zip(ranges).zip(values) {
+            if let Some(value) = value
+                && !range.contains(value)
+            {
+                return Err(format!("{name} {value} out of range"));
+            }
+        }
+
+        let Some(sigma) = self.config.spike_sigma else {
+            return Ok(());
+        };
+        let window = self.config.spike_window.get();
+        let history = self.history.entry(bdaddr).or_default();
+
+        let mut result = Ok(());
+        for (((name, min_deviation), history), value) in
+            QUANTITIES.iter().zip(history.iter_mut()).zip(values)
+        {
+            let Some(value) = value else { continue };
+            if result.is_ok()
+                && history.len() >= window
+                && let Some(median) = median(history.iter().copied())
+                && let Some(deviation) = std_deviation(history, median)
+                && (value - median).abs() > sigma * deviation.max(*min_deviation)
+            {
+                result = Err(format!("{name} {value} deviates from the median {median}"));
+            }
+            // Rejected values are kept too, so that real step changes are
+            // accepted once they dominate the window
+            history.push_back(value);
+            while history.len() > window {
+                history.pop_front();
+            }
+        }
+        result
+    }
+}
+
+/// Median of the values, or `None` if there are none.
+fn median(values: impl Iterator<Item = f32>) -> Option<f32> {
+    let mut sorted: Vec<f32> = values.collect();
+    sorted.sort_by(f32::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.is_empty() {
+        None
+    } else if sorted.len().is_multiple_of(2) {
+        Some(f32::midpoint(sorted[mid - 1], sorted[mid]))
+    } else {
+        Some(sorted[mid])
+    }
+}
+
+/// Standard deviation estimated from the median absolute deviation, which
+/// unlike the plain standard deviation is not inflated by the spikes.
+fn std_deviation(values: &VecDeque<f32>, median: f32) -> Option<f32> {
+    self::median(values.iter().map(|v| (v - median).abs())).map(|mad| 1.4826 * mad)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use ruuvi_sensor_protocol::SensorValues;
+
+    use super::*;
+
+    fn bdaddr() -> BDAddr {
+        BDAddr::from([1, 2, 3, 4, 5, 6])
+    }
+
+    fn make_validator(spike_sigma: Option<f32>) -> Validator {
+        Validator::new(config::Validation {
+            spike_sigma,
+            spike_window: NonZeroUsize::new(5).unwrap(),
+            ..config::Validation::default()
+        })
+    }
+
+    #[test]
+    fn accepts_plausible_values() {
+        let mut v = make_validator(None);
+        assert!(
+            v.check(bdaddr(), [Some(21.5), Some(40.0), Some(1013.0)])
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn accepts_missing_values() {
+        let mut v = make_validator(None);
+        assert!(v.check(bdaddr(), [None, None, None]).is_ok());
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let mut v = make_validator(None);
+        assert!(
+            v.check(bdaddr(), [Some(-163.0), Some(40.0), Some(1013.0)])
+                .is_err()
+        );
+        assert!(
+            v.check(bdaddr(), [Some(21.0), Some(40.0), Some(0.0)])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn spike_detection_needs_full_window() {
+        let mut v = make_validator(Some(3.0));
+        assert!(v.check(bdaddr(), [Some(20.0), None, None]).is_ok());
+        assert!(v.check(bdaddr(), [Some(60.0), None, None]).is_ok());
+    }
+
+    #[test]
+    fn rejects_spikes() {
+        let mut v = make_validator(Some(3.0));
+        for t in [20.0, 20.1, 20.0, 20.2, 20.1] {
+            assert!(v.check(bdaddr(), [Some(t), None, None]).is_ok());
+        }
+        assert!(v.check(bdaddr(), [Some(35.0), None, None]).is_err());
+        assert!(v.check(bdaddr(), [Some(20.1), None, None]).is_ok());
+    }
+
+    #[test]
+    fn accepts_step_change_after_it_dominates_the_window() {
+        let mut v = make_validator(Some(3.0));
+        for _ in 0..5 {
+            assert!(v.check(bdaddr(), [Some(20.0), None, None]).is_ok());
+        }
+        let results: Vec<bool> = (0..5)
+            .map(|_| v.check(bdaddr(), [Some(5.0), None, None]).is_ok())
+            .collect();
+        assert_eq!(results, [false, false, false, true, true]);
+    }
+
+    #[test]
+    fn median_of_even_and_odd_windows() {
+        assert!((median([3.0, 1.0, 2.0].into_iter()).unwrap() - 2.0).abs() < f32::EPSILON);
+        assert!((median([4.0, 1.0, 3.0, 2.0].into_iter()).unwrap() - 2.5).abs() < f32::EPSILON);
+        assert_eq!(median(std::iter::empty()), None);
+    }
+
+    #[test]
+    fn validates_humidity_before_the_calibration() {
+        // 98 % (raw 39200) in an otherwise empty v5 packet
+        let [h1, h2] = 39200_u16.to_be_bytes();
+        #[rustfmt::skip]
+        let payload = [
+            5, 0, 0, h1, h2, 0x4e, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        let values = SensorValues::from_manufacturer_specific_data(0x0499, payload).unwrap();
+        let mut sensor = SensorData::new(bdaddr(), 5, values);
+        sensor.calibrate(&config::Calibration {
+            humidity: Some(config::Adjustment {
+                gain: 1.0,
+                offset: 3.0,
+            }),
+            ..config::Calibration::default()
+        });
+        let mut v = make_validator(None);
+        assert_eq!(v.validate(&sensor), Ok(()));
+        assert_eq!(sensor.humidity(), Some(100.0));
+    }
+}
@@ -0,0 +1,369 @@
+//! Helpers for the end-to-end tests: a minimal MQTT broker stand-in and the
+//! daemon process.
+
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::BytesMut;
+use rumqttc::mqttbytes::Error as MqttError;
+use rumqttc::{
+    ConnAck, ConnectReturnCode, Packet, PubAck, Publish, QoS, SubAck, SubscribeReasonCode,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, Command};
+use tokio::sync::{Notify, mpsc};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+const MAX_PACKET_SIZE: usize = 1024 * 1024;
+
+/// How long to wait for the expected messages. The bridge retries the
+/// connection every 10 seconds.
+pub const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Message published by a client
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub client_id: String,
+    pub topic: String,
+    pub retain: bool,
+    pub payload: Vec<u8>,
+}
+
+impl Message {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.payload).unwrap()
+    }
+}
+
+/// In-process MQTT 3.1.1 broker with `QoS` 0 and 1, retained messages, and
+/// wildcard subscriptions. Good enough for the tests, nothing more.
+pub struct Broker {
+    pub port: u16,
+    state: Arc<Mutex<State>>,
+    published: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+#[derive(Default)]
+struct State {
+    messages: Vec<Message>,
+    retained: BTreeMap<String, Publish>,
+    sessions: Vec<Session>,
+    connections: Vec<JoinHandle<()>>,
+    /// Number of accepted connections
+    accepted: usize,
+    /// Protocol errors of the clients, failing the waits
+    errors: Vec<String>,
+}
+
+struct Session {
+    client_id: String,
+    filters: Vec<String>,
+    tx: mpsc::UnboundedSender<Packet>,
+}
+
+impl Broker {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let state = Arc::new(Mutex::new(State::default()));
+        let published = Arc::new(Notify::new());
+
+        let task = {
+            let state = state.clone();
+            let published = published.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let connection = tokio::spawn(serve(stream, state.clone(), published.clone()));
+                    let mut state = state.lock().unwrap();
+                    state.accepted += 1;
+                    state.connections.push(connection);
+                    published.notify_waiters();
+                }
+            })
+        };
+        Self {
+            port,
+            state,
+            published,
+            task,
+        }
+    }
+
+    /// All messages published so far
+    pub fn messages(&self) -> Vec<Message> {
+        self.state.lock().unwrap().messages.clone()
+    }
+
+    /// Number of accepted connections so far
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().accepted
+    }
+
+    /// Publishes a message to the subscribers as client `test`.
+    pub fn publish(&self, topic: &str, payload: &str, retain: bool) {
+        let mut publish = Publish::new(topic, QoS::AtMostOnce, payload);
+        publish.retain = retain;
+        on_publish(&self.state, "test", publish);
+        self.published.notify_waiters();
+    }
+
+    /// Waits until `count` published messages match the predicate, and
+    /// returns them.
+    pub async fn wait_for(
+        &self,
+        count: usize,
+        predicate: impl Fn(&Message) -> bool,
+    ) -> Vec<Message> {
+        self.wait_until(&format!("{count} messages"), || {
+            let messages: Vec<_> = self
+                .messages()
+                .into_iter()
+                .filter(|message| predicate(message))
+                .collect();
+            (messages.len() >= count).then_some(messages)
+        })
+        .await
+    }
+
+    /// Waits until `count` connections have been accepted.
+    pub async fn wait_for_connections(&self, count: usize) {
+        self.wait_until(&format!("{count} connections"), || {
+            (self.connections() >= count).then_some(())
+        })
+        .await;
+    }
+
+    /// Waits until the check returns a value after a publish or connection.
+    /// Fails on a timeout or a protocol error of a client.
+    async fn wait_until<T>(&self, what: &str, check: impl Fn() -> Option<T>) -> T {
+        let result = timeout(TIMEOUT, async {
+            loop {
+                let notified = self.published.notified();
+                let errors = self.state.lock().unwrap().errors.clone();
+                assert!(errors.is_empty(), "Broker errors: {errors:#?}");
+                if let Some(value) = check() {
+                    return value;
+                }
+                notified.await;
+            }
+        })
+        .await;
+        result.unwrap_or_else(|_| {
+            panic!(
+                "Timed out waiting for {what}. Published: {:#?}",
+                self.messages()
+            )
+        })
+    }
+
+    /// Drops all client connections.
+    pub fn disconnect_all(&self) {
+        let mut state = self.state.lock().unwrap();
+        for connection in state.connections.drain(..) {
+            connection.abort();
+        }
+        // Closes the connections by stopping the writers
+        state.sessions.clear();
+    }
+}
+
+impl Drop for Broker {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.disconnect_all();
+    }
+}
+
+async fn serve(stream: TcpStream, state: Arc<Mutex<State>>, published: Arc<Notify>) {
+    let (mut reader, mut writer) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Packet>();
+    let writer_task = tokio::spawn(async move {
+        while let Some(packet) = rx.recv().await {
+            let mut buf = BytesMut::new();
+            if packet.write(&mut buf, MAX_PACKET_SIZE).is_err()
+                || writer.write_all(&buf).await.is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    let mut client_id = String::new();
+    let mut buf = BytesMut::new();
+    loop {
+        let packet = match Packet::read(&mut buf, MAX_PACKET_SIZE) {
+            Ok(packet) => packet,
+            Err(MqttError::InsufficientBytes(_)) => match reader.read_buf(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(_) => continue,
+            },
+            Err(err) => {
+                on_error(
+                    &state,
+                    &published,
+                    format!("Invalid packet from '{client_id}': {err}"),
+                );
+                break;
+            }
+        };
+        match packet {
+            Packet::Connect(connect) => {
+                client_id = connect.client_id;
+                state.lock().unwrap().sessions.push(Session {
+                    client_id: client_id.clone(),
+                    filters: Vec::new(),
+                    tx: tx.clone(),
+                });
+                let _ = tx.send(Packet::ConnAck(ConnAck::new(
+                    ConnectReturnCode::Success,
+                    false,
+                )));
+            }
+            Packet::Subscribe(subscribe) => {
+                let mut state = state.lock().unwrap();
+                let filters: Vec<_> = subscribe.filters.iter().map(|f| f.path.clone()).collect();
+                let codes = filters
+                    .iter()
+                    .map(|_| SubscribeReasonCode::Success(QoS::AtMostOnce))
+                    .collect();
+                let _ = tx.send(Packet::SubAck(SubAck::new(subscribe.pkid, codes)));
+                for publish in state.retained.values() {
+                    if filters.iter().any(|filter| matches(filter, &publish.topic)) {
+                        let _ = tx.send(Packet::Publish(forwarded(publish)));
+                    }
+                }
+                if let Some(session) = state
+                    .sessions
+                    .iter_mut()
+                    .find(|session| session.client_id == client_id)
+                {
+                    session.filters.extend(filters);
+                }
+            }
+            Packet::Publish(publish) => {
+                if publish.qos != QoS::AtMostOnce {
+                    let _ = tx.send(Packet::PubAck(PubAck::new(publish.pkid)));
+                }
+                on_publish(&state, &client_id, publish);
+                published.notify_waiters();
+            }
+            Packet::PingReq => {
+                let _ = tx.send(Packet::PingResp);
+            }
+            Packet::Disconnect => break,
+            packet => {
+                on_error(
+                    &state,
+                    &published,
+                    format!("Unsupported packet from '{client_id}': {packet:?}"),
+                );
+                break;
+            }
+        }
+    }
+    writer_task.abort();
+    state
+        .lock()
+        .unwrap()
+        .sessions
+        .retain(|session| session.client_id != client_id);
+}
+
+fn on_error(state: &Mutex<State>, published: &Notify, error: String) {
+    state.lock().unwrap().errors.push(error);
+    published.notify_waiters();
+}
+
+fn on_publish(state: &Mutex<State>, client_id: &str, publish: Publish) {
+    let mut state = state.lock().unwrap();
+    state.messages.push(Message {
+        client_id: client_id.to_string(),
+        topic: publish.topic.clone(),
+        retain: publish.retain,
+        payload: publish.payload.to_vec(),
+    });
+    for session in &state.sessions {
+        if session
+            .filters
+            .iter()
+            .any(|filter| matches(filter, &publish.topic))
+        {
+            let _ = session.tx.send(Packet::Publish(forwarded(&publish)));
+        }
+    }
+    if publish.retain {
+        if publish.payload.is_empty() {
+            state.retained.remove(&publish.topic);
+        } else {
+            state.retained.insert(publish.topic.clone(), publish);
+        }
+    }
+}
+
+/// Copy of the message delivered with `QoS` 0
+fn forwarded(publish: &Publish) -> Publish {
+    let mut publish = publish.clone();
+    publish.qos = QoS::AtMostOnce;
+    publish.pkid = 0;
+    publish
+}
+
+/// Whether the topic matches a subscription filter with `+` and `#` wildcards
+fn matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for part in filter.split('/') {
+        match (part, levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// The bridge process, killed when dropped
+pub struct Daemon {
+    child: Child,
+    /// Working directory with the configuration file
+    _dir: tempfile::TempDir,
+}
+
+impl Daemon {
+    /// Starts the bridge with the given configuration, connecting to the
+    /// broker.
+    pub fn start(broker: &Broker, client_id: &str, config: &str, args: &[&str]) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ruuvi2mqtt.yaml"), config).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_ruuvi2mqtt"))
+            .current_dir(dir.path())
+            .args(args)
+            .env_remove("RUST_LOG")
+            .env("LOG_LEVEL", "off")
+            .env("RUUVI2MQTT_MQTT__SERVER", "127.0.0.1")
+            .env("RUUVI2MQTT_MQTT__PORT", broker.port.to_string())
+            .env("RUUVI2MQTT_MQTT__CLIENT_ID", client_id)
+            .stdout(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        Self { child, _dir: dir }
+    }
+
+    /// Sends SIGTERM and waits for the process to exit.
+    pub async fn terminate(mut self) {
+        let pid = self.child.id().unwrap().to_string();
+        let status = std::process::Command::new("kill")
+            .args(["-TERM", &pid])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let status = timeout(TIMEOUT, self.child.wait()).await.unwrap().unwrap();
+        assert!(status.success(), "{status}");
+    }
+}
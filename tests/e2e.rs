@@ -0,0 +1,224 @@
+//! End-to-end tests running the bridge with simulated tags against an
+//! in-process MQTT broker.
+
+mod common;
+
+use common::{Broker, Daemon, Message};
+
+const DEVICES: &str = "
+devices:
+  AA:BB:CC:DD:EE:01:
+    name: Sauna
+";
+
+const STATE_TOPIC: &str = "ruuvi2mqtt/aabbccddee01";
+
+fn is_state(message: &Message) -> bool {
+    message.topic == STATE_TOPIC
+}
+
+fn is_availability(message: &Message) -> bool {
+    message.topic == "ruuvi2mqtt/bridge/test/state"
+}
+
+#[tokio::test]
+async fn publishes_discovery_and_state() {
+    let broker = Broker::start().await;
+    let daemon = Daemon::start(&broker, "test", DEVICES, &["--simulate", "100ms"]);
+
+    // The manifest is published last on connect
+    broker
+        .wait_for(1, |m| m.topic == "ruuvi2mqtt/bridge/test/discovery")
+        .await;
+    let states = broker.wait_for(1, is_state).await;
+    assert_eq!(states[0].client_id, "test");
+    assert!(!states[0].retain);
+    let state = states[0].json();
+    for key in ["temperature", "humidity", "pressure", "battery", "tx_power"] {
+        assert!(state[key].is_number(), "{key}: {state}");
+    }
+    assert_eq!(state["gateway"], "test");
+    assert_eq!(state["movement"], false);
+
+    let messages = broker.messages();
+    let availability: Vec<_> = messages.iter().filter(|m| is_availability(m)).collect();
+    assert_eq!(availability.len(), 1);
+    assert_eq!(availability[0].payload, b"online");
+    assert!(availability[0].retain);
+
+    let discovery: Vec<_> = messages
+        .iter()
+        .filter(|m| m.topic.starts_with("homeassistant/"))
+        .collect();
+    let topics: Vec<_> = discovery.iter().map(|m| m.topic.as_str()).collect();
+    assert_eq!(
+        topics,
+        [
+            "homeassistant/sensor/ruuvi_aabbccddee01/temperature/config",
+            "homeassistant/sensor/ruuvi_aabbccddee01/humidity/config",
+            "homeassistant/sensor/ruuvi_aabbccddee01/pressure/config",
+            "homeassistant/sensor/ruuvi_aabbccddee01/battery/config",
+            "homeassistant/binary_sensor/ruuvi_aabbccddee01/battery_low/config",
+            "homeassistant/sensor/ruuvi_aabbccddee01/tx_power/config",
+            "homeassistant/sensor/ruuvi_aabbccddee01/rejected_readings/config",
+            "homeassistant/binary_sensor/ruuvi_aabbccddee01/movement/config",
+            "homeassistant/sensor/ruuvi_aabbccddee01/battery_level/config",
+            "homeassistant/sensor/ruuvi_aabbccddee01/packet_loss/config",
+        ]
+    );
+    assert!(discovery.iter().all(|m| m.retain));
+    let temperature = discovery[0].json();
+    assert_eq!(temperature["name"], "Sauna Temperature");
+    assert_eq!(temperature["state_topic"], STATE_TOPIC);
+    assert_eq!(temperature["device"]["identifiers"][0], "AA:BB:CC:DD:EE:01");
+
+    let manifest = messages
+        .iter()
+        .find(|m| m.topic == "ruuvi2mqtt/bridge/test/discovery")
+        .unwrap();
+    assert!(manifest.retain);
+    assert_eq!(manifest.json(), serde_json::json!(topics));
+
+    daemon.terminate().await;
+    let availability = broker.wait_for(2, is_availability).await;
+    assert_eq!(availability[1].payload, b"offline");
+    assert!(availability[1].retain);
+}
+
+#[tokio::test]
+async fn throttles_across_instances() {
+    let broker = Broker::start().await;
+    let _b = Daemon::start(&broker, "b", &throttle(1), &["--simulate", "100ms"]);
+    broker.wait_for(1, is_state).await;
+
+    // The updates of `b` reset the throttling of `a`, so `a` publishes only
+    // its first reading, which it can't know to throttle
+    let _a = Daemon::start(&broker, "a", &throttle(2), &["--simulate", "100ms"]);
+    broker
+        .wait_for(1, |m| is_state(m) && m.client_id == "a")
+        .await;
+    let count = broker.messages().iter().filter(|m| is_state(m)).count();
+    broker.wait_for(count + 3, is_state).await;
+
+    let states: Vec<_> = broker
+        .messages()
+        .into_iter()
+        .filter(is_state)
+        .map(|m| m.client_id)
+        .collect();
+    assert_eq!(
+        states.iter().filter(|id| *id == "a").count(),
+        1,
+        "{states:?}"
+    );
+}
+
+#[tokio::test]
+async fn reconnects_to_broker() {
+    let broker = Broker::start().await;
+    let _daemon = Daemon::start(&broker, "test", &throttle(0), &["--simulate", "100ms"]);
+    broker.wait_for(1, is_state).await;
+
+    broker.disconnect_all();
+    broker.wait_for_connections(2).await;
+    let reconnected = broker.messages().len();
+
+    // Announces itself and the devices again
+    let online = |m: &Message| is_availability(m) && m.payload == b"online";
+    broker.wait_for(2, online).await;
+    assert_eq!(broker.connections(), 2);
+    broker
+        .wait_for(2, |m| {
+            m.topic == "homeassistant/sensor/ruuvi_aabbccddee01/temperature/config"
+        })
+        .await;
+    let count = broker.messages().iter().filter(|m| is_state(m)).count();
+    broker.wait_for(count + 1, is_state).await;
+    assert!(broker.messages().len() > reconnected);
+
+    // Subscribes to the commands again
+    broker.publish(
+        "ruuvi2mqtt/bridge/command",
+        r#"{"command": "publish_all", "id": 1}"#,
+        false,
+    );
+    let responses = broker
+        .wait_for(1, |m| m.topic == "ruuvi2mqtt/bridge/response")
+        .await;
+    let response = responses[0].json();
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["status"], "ok");
+}
+
+#[tokio::test]
+async fn ignores_retained_commands() {
+    let broker = Broker::start().await;
+    let command = |id: u32| format!(r#"{{"command": "publish_all", "id": {id}}}"#);
+    broker.publish("ruuvi2mqtt/bridge/command", &command(1), true);
+    let _daemon = Daemon::start(&broker, "test", DEVICES, &["--simulate", "100ms"]);
+    broker
+        .wait_for(1, |m| m.topic == "ruuvi2mqtt/bridge/test/discovery")
+        .await;
+
+    broker.publish("ruuvi2mqtt/bridge/command", &command(2), false);
+    let is_response = |m: &Message| m.topic == "ruuvi2mqtt/bridge/response";
+    broker.wait_for(1, is_response).await;
+    let ids: Vec<_> = broker
+        .messages()
+        .into_iter()
+        .filter(is_response)
+        .map(|m| m.json()["id"].clone())
+        .collect();
+    assert_eq!(ids, [2]);
+}
+
+#[tokio::test]
+async fn republishes_discovery_when_home_assistant_starts() {
+    let broker = Broker::start().await;
+    let _daemon = Daemon::start(&broker, "test", DEVICES, &["--simulate", "100ms"]);
+    let is_config =
+        |m: &Message| m.topic == "homeassistant/sensor/ruuvi_aabbccddee01/temperature/config";
+    let is_manifest = |m: &Message| m.topic == "ruuvi2mqtt/bridge/test/discovery";
+    broker.wait_for(1, is_manifest).await;
+
+    broker.publish("homeassistant/status", "online", false);
+    broker.wait_for(2, is_config).await;
+    broker.wait_for(2, is_manifest).await;
+    assert_eq!(broker.connections(), 1);
+}
+
+#[tokio::test]
+async fn keeps_entities_of_other_bridges() {
+    let broker = Broker::start().await;
+    let shared = "homeassistant/sensor/ruuvi_aabbccddee02/temperature/config";
+    let removed = "homeassistant/sensor/ruuvi_aabbccddee03/temperature/config";
+    broker.publish(
+        "ruuvi2mqtt/bridge/other/discovery",
+        &serde_json::json!([shared]).to_string(),
+        true,
+    );
+    broker.publish(
+        "ruuvi2mqtt/bridge/test/discovery",
+        &serde_json::json!([shared, removed]).to_string(),
+        true,
+    );
+    let _daemon = Daemon::start(&broker, "test", &throttle(0), &["--simulate", "100ms"]);
+
+    let cleared = |m: &Message| m.client_id == "test" && m.payload.is_empty();
+    let removals = broker.wait_for(1, cleared).await;
+    assert_eq!(removals[0].topic, removed);
+    // The next state follows the handling of the manifests
+    let count = broker.messages().iter().filter(|m| is_state(m)).count();
+    broker.wait_for(count + 1, is_state).await;
+    let removals: Vec<_> = broker
+        .messages()
+        .into_iter()
+        .filter(cleared)
+        .map(|m| m.topic)
+        .collect();
+    assert_eq!(removals, [removed]);
+}
+
+fn throttle(seconds: u32) -> String {
+    format!("{DEVICES}\nmqtt:\n  throttle: {seconds}\n")
+}